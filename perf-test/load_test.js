@@ -47,7 +47,6 @@ export default function () {
         token: Settings.TOKEN,
         url: url,
         method: "GET",
-        issuer: "http://issuer.local",
         headers: {
             "User-Agent": "Apple Mozilla Edge",
             "X-FWF-Custom-Header": "Lorem"
@@ -1,4 +1,8 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    rc::Rc,
+    time::{Instant, SystemTime},
+};
 
 use anyhow::{Context, Result};
 use common_access_token::{
@@ -6,19 +10,39 @@ use common_access_token::{
     RegisteredClaims, TokenBuilder,
 };
 use garde::Validate;
+use percent_encoding::percent_decode_str;
 use serde_json::json;
 use spin_sdk::http::{IntoResponse, Params, Request, Response, ResponseBuilder};
 
 use crate::{
-    api::models::{GenerateTokenRequestModel, ItemsModel, ValidateTokenRequestModel},
-    persistence::{BlockedClaimType, Persistence},
-    validator::{Cat, CatValidationOptions},
+    api::{
+        models::{
+            ClaimTemplateModel, GenerateTokenRequestModel, IssuerPolicyModel, ItemsModel,
+            MintTokenRequestModel, RevokeTokenModel, TenantModel, ValidateTokenRequestModel,
+            ValidationProfileModel,
+        },
+        tenancy,
+    },
+    decision_log::{self, Decision},
+    geoip::{self, ClientLocation},
+    metrics, minting,
+    persistence::{
+        canonical_cti, BlockedClaimType, HostConflictError, IssuerPolicy, Persistence,
+        RevokedToken, Settings, ValidationProfile,
+    },
+    telemetry::{self, TraceContext},
+    validator::{
+        Cat, ConditionalResponse, CustomClaimValidator, FailureCode, Observation, ProofError,
+        RateLimitError, Region,
+    },
 };
-// this could be wizened
-const KEY: &str = "my-super-fancy-and-secret-key";
 
-pub fn get_blocking_data(_: Request, _: Params) -> Result<impl IntoResponse> {
-    let data = Persistence::get_blocking_data()?;
+pub fn get_blocking_data(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_blocking_data()?;
     Ok(ResponseBuilder::new(200)
         .header("content-type", "application/json")
         .body(data)
@@ -26,6 +50,10 @@ pub fn get_blocking_data(_: Request, _: Params) -> Result<impl IntoResponse> {
 }
 
 pub fn remove_items_from_blocklist(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
     let Some(kind) = p.get("kind") else {
         return Ok(Response::new(400, "Bad Request"));
     };
@@ -37,13 +65,31 @@ pub fn remove_items_from_blocklist(req: Request, p: Params) -> Result<impl IntoR
         return Ok(Response::new(400, "Bad Request"));
     };
 
-    match Persistence::remove_items_from_blocklist(kind, model.values) {
-        Ok(_) => Ok(Response::new(200, ())),
-        Err(_) => Ok(Response::new(500, ())),
+    let mut values = model.values;
+    if kind == BlockedClaimType::Country {
+        // entries stored before countries were normalized are removed by their raw value
+        let canonical = values
+            .iter()
+            .filter_map(|value| Region::canonical(value))
+            .collect::<Vec<String>>();
+        values.extend(canonical);
+        values.sort();
+        values.dedup();
     }
+    let result = persistence.remove_items_from_blocklist(kind, values.clone());
+    Ok(audited(
+        &persistence,
+        "remove_from_blocklist",
+        values,
+        result,
+    ))
 }
 
 pub fn add_items_to_blocklist(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
     let Some(kind) = p.get("kind") else {
         return Ok(Response::new(400, "Bad Request"));
     };
@@ -55,76 +101,678 @@ pub fn add_items_to_blocklist(req: Request, p: Params) -> Result<impl IntoRespon
         return Ok(Response::new(400, "Bad Request"));
     };
 
-    match Persistence::add_items_to_blocklist(kind, model.values) {
-        Ok(_) => Ok(Response::new(200, ())),
-        Err(_) => Ok(Response::new(500, ())),
-    }
+    // countries are stored in their canonical form, which the blocklist check looks up
+    let values = match kind {
+        BlockedClaimType::Country => {
+            let Some(values) = model
+                .values
+                .iter()
+                .map(|value| Region::canonical(value))
+                .collect::<Option<Vec<String>>>()
+            else {
+                return Ok(Response::new(
+                    400,
+                    "Bad Request (countries must be ISO 3166 codes)",
+                ));
+            };
+            values
+        }
+        _ => model.values,
+    };
+
+    let result = persistence.add_items_to_blocklist(kind, values.clone());
+    Ok(audited(&persistence, "add_to_blocklist", values, result))
 }
 
-pub fn remove_asns_from_blocklist(req: Request, _: Params) -> Result<impl IntoResponse> {
+pub fn remove_asns_from_blocklist(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
     let Ok(model) = serde_json::from_slice::<ItemsModel<u32>>(req.body()) else {
         return Ok(Response::new(400, "Bad Request"));
     };
 
-    match Persistence::remove_asns_from_blocklist(model.values) {
-        Ok(_) => Ok(Response::new(200, ())),
-        Err(_) => Ok(Response::new(500, ())),
-    }
+    let values = model.values.iter().map(|asn| asn.to_string()).collect();
+    let result = persistence.remove_asns_from_blocklist(model.values);
+    Ok(audited(
+        &persistence,
+        "remove_asns_from_blocklist",
+        values,
+        result,
+    ))
 }
 
-pub async fn add_asns_to_blocklist(req: Request, _: Params) -> Result<impl IntoResponse> {
+pub async fn add_asns_to_blocklist(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
     let Ok(model) = serde_json::from_slice::<ItemsModel<u32>>(req.body()) else {
         return Ok(Response::new(400, "Bad Request"));
     };
 
-    match Persistence::add_asns_to_blocklist(model.values).await {
-        Ok(_) => Ok(Response::new(200, ())),
-        Err(_) => Ok(Response::new(500, ())),
+    let values = model.values.iter().map(|asn| asn.to_string()).collect();
+    let result = persistence.add_asns_to_blocklist(model.values).await;
+    Ok(audited(
+        &persistence,
+        "add_asns_to_blocklist",
+        values,
+        result,
+    ))
+}
+
+pub fn get_revoked_tokens(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_revoked_tokens()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn revoke_tokens(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ItemsModel<RevokeTokenModel>>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+    let Some(values) = model
+        .values
+        .into_iter()
+        .map(|value| {
+            canonical_cti(&value.cti).map(|cti| RevokedToken {
+                cti,
+                exp: value.exp,
+            })
+        })
+        .collect::<Option<Vec<RevokedToken>>>()
+    else {
+        return Ok(Response::new(
+            400,
+            "Bad Request (cti must be base64url encoded)",
+        ));
+    };
+
+    let ctis = values.iter().map(|value| value.cti.clone()).collect();
+    let result = persistence.revoke_tokens(values);
+    Ok(audited(&persistence, "revoke_tokens", ctis, result))
+}
+
+pub fn unrevoke_tokens(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ItemsModel<String>>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+    let Some(ctis) = model
+        .values
+        .iter()
+        .map(|cti| canonical_cti(cti))
+        .collect::<Option<Vec<String>>>()
+    else {
+        return Ok(Response::new(
+            400,
+            "Bad Request (cti must be base64url encoded)",
+        ));
+    };
+
+    let values = ctis.clone();
+    let result = persistence.unrevoke_tokens(ctis);
+    Ok(audited(&persistence, "unrevoke_tokens", values, result))
+}
+
+pub fn get_issuers(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_issuers()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn upsert_issuer(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<IssuerPolicyModel>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    if let Err(e) = model.validate() {
+        return Ok(Response::new(400, format!("Bad Request ({})", e)));
+    };
+
+    if !model.has_active_key() {
+        return Ok(Response::new(
+            400,
+            "Bad Request (active_kid is not part of the keys)",
+        ));
     }
+
+    let values = vec![model.issuer.clone()];
+    let result = persistence.upsert_issuer(model.into());
+    Ok(audited(&persistence, "upsert_issuer", values, result))
 }
 
-pub async fn validate_token_simple(req: Request, _: Params) -> Result<impl IntoResponse> {
-    let Ok(model) = serde_json::from_slice::<ValidateTokenRequestModel>(req.body()) else {
+pub fn remove_issuers(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ItemsModel<String>>(req.body()) else {
         return Ok(Response::new(400, "Bad Request"));
     };
-    let Ok(decoded_token) = base64_url::decode(model.token.as_str()) else {
+
+    let values = model.values.clone();
+    let result = persistence.remove_issuers(model.values);
+    Ok(audited(&persistence, "remove_issuers", values, result))
+}
+
+pub fn get_profiles(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_profiles()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn upsert_profile(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ValidationProfileModel>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    if let Err(e) = model.validate() {
+        return Ok(Response::new(400, format!("Bad Request ({})", e)));
+    };
+
+    let values = vec![model.name.clone()];
+    let result = persistence.upsert_profile(model.into());
+    Ok(audited(&persistence, "upsert_profile", values, result))
+}
+
+pub fn remove_profiles(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ItemsModel<String>>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    let values = model.values.clone();
+    let result = persistence.remove_profiles(model.values);
+    Ok(audited(&persistence, "remove_profiles", values, result))
+}
+
+pub fn get_sessions(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Some(subject) = session_subject(&p) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+    let data = persistence.get_sessions(&subject)?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn kick_sessions(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Some(subject) = session_subject(&p) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+    let Ok(model) = serde_json::from_slice::<ItemsModel<String>>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    let values = model.values.clone();
+    let result = persistence.kick_sessions(&subject, model.values);
+    Ok(audited(&persistence, "kick_sessions", values, result))
+}
+
+// the subject is the rest of the path (it may contain /), percent-encoded characters are decoded
+fn session_subject(p: &Params) -> Option<String> {
+    let subject = percent_decode_str(p.wildcard()?).decode_utf8().ok()?;
+    (!subject.is_empty()).then(|| subject.into_owned())
+}
+
+pub fn get_templates(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_templates()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn upsert_template(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ClaimTemplateModel>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    if let Err(e) = model.validate() {
+        return Ok(Response::new(400, format!("Bad Request ({})", e)));
+    };
+
+    let values = vec![model.name.clone()];
+    let result = persistence.upsert_template(model.into());
+    Ok(audited(&persistence, "upsert_template", values, result))
+}
+
+pub fn remove_templates(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<ItemsModel<String>>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    let values = model.values.clone();
+    let result = persistence.remove_templates(model.values);
+    Ok(audited(&persistence, "remove_templates", values, result))
+}
+
+// issuance for production use, authorized by the minting key of the issuer
+pub fn mint_token(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(model) = serde_json::from_slice::<MintTokenRequestModel>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    if let Err(e) = model.validate() {
+        return Ok(Response::new(400, format!("Bad Request ({})", e)));
+    };
+
+    let (Ok(issuers), Ok(templates)) = (persistence.get_issuers(), persistence.get_templates())
+    else {
+        return Ok(Response::new(500, ()));
+    };
+    let Some(policy) = issuers.find(&model.issuer) else {
+        return Ok(Response::new(401, "Unauthorized"));
+    };
+    let authorized = tenancy::bearer(&req)
+        .zip(policy.minting_key_hash.as_ref())
+        .is_some_and(|(key, hash)| tenancy::key_matches(key, hash));
+    if !authorized {
+        return Ok(Response::new(401, "Unauthorized"));
+    }
+    let Some(template) = templates.find(&model.template) else {
+        return Ok(Response::new(400, "Bad Request (claim template not found)"));
+    };
+    let Some(key) = policy.signing_key() else {
+        return Ok(Response::new(500, ()));
+    };
+
+    let claims = model.into_token_claims(template, minting::random_cti()?);
+    if policy
+        .max_token_lifetime
+        .is_some_and(|max| claims.lifetime > max)
+    {
         return Ok(Response::new(
             400,
-            "Bad Request (could not decode Common Access Token",
+            "Bad Request (lifetime exceeds max_token_lifetime of the issuer)",
         ));
+    }
+    let token = minting::mint(key, &claims)?;
+    let token_bytes = token.to_bytes().with_context(|| "Failed to encode token")?;
+    let payload = serde_json::to_string_pretty(&json!({
+        "token": base64_url::encode(&token_bytes),
+        "claims": minting::claims_json(&token.claims),
+    }))
+    .with_context(|| "Failed to serialize response payload")?;
+
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(payload)
+        .build())
+}
+
+pub fn get_tenants(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    let data = Persistence::get_tenants()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn upsert_tenant(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    let Ok(model) = serde_json::from_slice::<TenantModel>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
     };
 
-    match Cat::new(KEY)
-        .validate(&decoded_token, model.into_non_kv_validation_options())
-        .await
+    if let Err(e) = model.validate() {
+        return Ok(Response::new(400, format!("Bad Request ({})", e)));
+    };
+
+    let values = vec![model.id.clone()];
+    let result = Persistence::upsert_tenant(model.into());
+    if let Some(e) = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<HostConflictError>())
     {
-        Ok(_) => Ok(Response::new(200, ())),
-        Err(e) => Ok(Response::new(403, format!("{}", e))),
+        return Ok(Response::new(409, format!("Conflict ({})", e)));
     }
+    Ok(audited(
+        &Persistence::default_scope(),
+        "upsert_tenant",
+        values,
+        result,
+    ))
+}
+
+pub fn remove_tenants(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    let Ok(model) = serde_json::from_slice::<ItemsModel<String>>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    let values = model.values.clone();
+    let result = Persistence::remove_tenants(model.values);
+    Ok(audited(
+        &Persistence::default_scope(),
+        "remove_tenants",
+        values,
+        result,
+    ))
+}
+
+pub fn get_audit_log(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_audit_log()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+// successful changes are recorded in the audit log of the scope they were made in
+fn audited(
+    persistence: &Persistence,
+    action: &str,
+    values: Vec<String>,
+    result: Result<()>,
+) -> Response {
+    match result.and_then(|_| persistence.append_audit_entry(action, values)) {
+        Ok(_) => Response::new(200, ()),
+        Err(_) => Response::new(500, ()),
+    }
+}
+
+pub async fn validate_token_simple(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    validate_token_with(req, &persistence, ValidationProfile::simple()).await
+}
+
+pub async fn validate_token(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    validate_token_with(req, &persistence, ValidationProfile::full()).await
 }
 
-pub async fn validate_token(req: Request, _: Params) -> Result<impl IntoResponse> {
-    let Ok(model) = serde_json::from_slice::<ValidateTokenRequestModel>(req.body()) else {
+pub async fn validate_token_with_profile(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Some(name) = p.get("profile") else {
         return Ok(Response::new(400, "Bad Request"));
     };
+    let Ok(profiles) = persistence.get_profiles() else {
+        return Ok(Response::new(500, ()));
+    };
+    let Some(profile) = profiles.find(name) else {
+        return Ok(Response::new(404, "Validation profile not found"));
+    };
+    validate_token_with(req, &persistence, profile.clone()).await
+}
+
+async fn validate_token_with(
+    req: Request,
+    persistence: &Persistence,
+    profile: ValidationProfile,
+) -> Result<Response> {
+    let (start, started) = (SystemTime::now(), Instant::now());
+    let trace = TraceContext::from_request(&req);
+    let (Ok(issuers), Ok(settings)) = (
+        persistence.get_trusted_issuers(),
+        persistence.get_settings(),
+    ) else {
+        return Ok(Response::new(500, ()));
+    };
+    let (model, decoded_token) = match parse_validation_request(req.body()) {
+        Ok(request) => request,
+        Err(reason) => {
+            // malformed requests are decisions as well
+            let observation = Observation {
+                failure: Some(FailureCode::Malformed),
+                ..Default::default()
+            };
+            _ = metrics::record(&observation);
+            decision_log::log(
+                &settings.decision_log,
+                &Decision {
+                    tenant: persistence.tenant(),
+                    profile: &profile.name,
+                    client_ip: None,
+                    country: None,
+                    duration: started.elapsed(),
+                },
+                &observation,
+            );
+            return Ok(Response::new(400, reason));
+        }
+    };
+    let profile = profile.with_default_claims(&settings.claims);
+    let client_ip = model.client_ip.clone();
+    // callers may provide the country, otherwise it's looked up in the offline GeoIP database
+    let location = Rc::new(ClientLocation::new(
+        model.client_ip.clone(),
+        model.country.clone(),
+    ));
+    let Ok(opts) = model.into_validation_options(&settings, &profile, location.clone()) else {
+        return Ok(Response::new(500, ()));
+    };
+    let mut observation = Observation::default();
+    let result = Cat::new(&issuers, persistence)
+        .validate(&decoded_token, opts, &mut observation)
+        .await;
+    // metrics are best effort and never affect the decision
+    _ = metrics::record(&observation);
+    decision_log::log(
+        &settings.decision_log,
+        &Decision {
+            tenant: persistence.tenant(),
+            profile: &profile.name,
+            client_ip: Some(&client_ip),
+            country: location.known_country(),
+            duration: started.elapsed(),
+        },
+        &observation,
+    );
+    _ = telemetry::export(&trace, "validate", start, started.elapsed(), &observation);
+    let mut response = match result {
+        Ok(_) => Response::new(200, ()),
+        Err(e) => validation_failed(e),
+    };
+    response.set_header("traceparent", trace.traceparent());
+    Ok(response)
+}
 
+// the validation request along with the decoded token, or the reason it's rejected with
+fn parse_validation_request(body: &[u8]) -> Result<(ValidateTokenRequestModel, Vec<u8>), String> {
+    let Ok(model) = serde_json::from_slice::<ValidateTokenRequestModel>(body) else {
+        return Err(String::from("Bad Request"));
+    };
+    if let Err(e) = model.validate() {
+        return Err(format!("Bad Request ({})", e));
+    };
     let Ok(decoded_token) = base64_url::decode(model.token.as_str()) else {
-        return Ok(Response::new(
-            400,
+        return Err(String::from(
             "Bad Request (could not decode Common Access Token)",
         ));
     };
+    Ok((model, decoded_token))
+}
 
-    match Cat::new(KEY)
-        .validate(&decoded_token, CatValidationOptions::from(model))
-        .await
-    {
-        Ok(_) => Ok(Response::new(200, ())),
-        Err(e) => Ok(Response::new(403, format!("{}", e))),
+// the token may ask for a specific response (catif), a failed proof of possession
+// is signaled as a DPoP challenge (RFC 9449), exceeded rate limits as 429
+fn validation_failed(e: anyhow::Error) -> Response {
+    if let Some(conditional) = e.downcast_ref::<ConditionalResponse>() {
+        let mut builder = ResponseBuilder::new(conditional.status);
+        for (name, value) in &conditional.headers {
+            builder.header(name, value);
+        }
+        return builder.body(conditional.reason.clone()).build();
+    }
+    if let Some(limited) = e.downcast_ref::<RateLimitError>() {
+        return ResponseBuilder::new(429)
+            .header("retry-after", limited.retry_after.to_string())
+            .body(format!("{}", e))
+            .build();
+    }
+    match e.downcast_ref::<ProofError>() {
+        Some(_) => ResponseBuilder::new(401)
+            .header("www-authenticate", "DPoP error=\"invalid_dpop_proof\"")
+            .body(format!("{}", e))
+            .build(),
+        None => Response::new(403, format!("{}", e)),
     }
 }
 
-pub fn generate_test_token(req: Request, _: Params) -> Result<impl IntoResponse> {
+pub fn get_metrics(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    let data = Persistence::get_metrics()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(metrics::render(&data))
+        .build())
+}
+
+pub fn get_settings(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let data = persistence.get_settings()?;
+    Ok(ResponseBuilder::new(200)
+        .header("content-type", "application/json")
+        .body(data)
+        .build())
+}
+
+pub fn set_settings(req: Request, p: Params) -> Result<impl IntoResponse> {
+    let persistence = match tenancy::resolve_admin(&req, &p) {
+        Ok(persistence) => persistence,
+        Err(response) => return Ok(response),
+    };
+    let Ok(settings) = serde_json::from_slice::<Settings>(req.body()) else {
+        return Ok(Response::new(400, "Bad Request"));
+    };
+
+    for (claim_key, rule) in &settings.custom_claims {
+        if let Err(e) = CustomClaimValidator::verify_rule(*claim_key, rule) {
+            return Ok(Response::new(400, format!("Bad Request ({})", e)));
+        }
+    }
+
+    let result = persistence.set_settings(settings);
+    Ok(audited(&persistence, "set_settings", vec![], result))
+}
+
+pub fn set_geoip_database(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    if let Err(e) = geoip::verify_database(req.body()) {
+        return Ok(Response::new(400, format!("Bad Request ({})", e)));
+    }
+
+    let result = Persistence::set_geoip_database(req.body());
+    Ok(audited(
+        &Persistence::default_scope(),
+        "set_geoip_database",
+        vec![],
+        result,
+    ))
+}
+
+pub fn remove_geoip_database(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    let result = Persistence::remove_geoip_database();
+    Ok(audited(
+        &Persistence::default_scope(),
+        "remove_geoip_database",
+        vec![],
+        result,
+    ))
+}
+
+// test tokens are signed by the test issuer, never with the keys of configured issuers
+pub fn generate_test_token(req: Request, p: Params) -> Result<impl IntoResponse> {
+    if let Err(response) = tenancy::require_operator(&req, &p) {
+        return Ok(response);
+    }
+    let Some(policy) = IssuerPolicy::test_issuer() else {
+        return Ok(Response::new(404, "Not Found"));
+    };
     let Ok(model) = serde_json::from_slice::<GenerateTokenRequestModel>(req.body()) else {
         return Ok(Response::new(400, "Bad Request"));
     };
@@ -133,6 +781,16 @@ pub fn generate_test_token(req: Request, _: Params) -> Result<impl IntoResponse>
         return Ok(Response::new(400, format!("Bad Request ({})", e)));
     };
 
+    if model.issuer != policy.issuer {
+        return Ok(Response::new(
+            400,
+            format!("Bad Request (test tokens are issued by {})", policy.issuer),
+        ));
+    }
+    let Some(key) = policy.signing_key() else {
+        return Ok(Response::new(500, ()));
+    };
+
     let now = current_timestamp();
 
     let mut catu_components = BTreeMap::new();
@@ -146,9 +804,9 @@ pub fn generate_test_token(req: Request, _: Params) -> Result<impl IntoResponse>
     );
 
     let allowed_methods = vec!["GET"];
-    let token = TokenBuilder::new()
+    let mut builder = TokenBuilder::new()
         .algorithm(Algorithm::HmacSha256)
-        .protected_key_id(KeyId::string("my-key-id"))
+        .protected_key_id(KeyId::string(key.kid.clone()))
         .registered_claims(
             RegisteredClaims::new()
                 .with_issuer(model.issuer.clone())
@@ -180,8 +838,15 @@ pub fn generate_test_token(req: Request, _: Params) -> Result<impl IntoResponse>
             map.insert(3, CborValue::Text("X-FWF-Custom-Header".to_string()));
             map.insert(4, CborValue::Map(x_map));
             map
-        })
-        .sign(KEY.as_bytes())
+        });
+    if let Some(alpn) = model.alpn.filter(|alpn| !alpn.is_empty()) {
+        builder = builder.custom_array(
+            cat_keys::CATALPN,
+            alpn.into_iter().map(CborValue::Text).collect(),
+        );
+    }
+    let token = builder
+        .sign(key.secret.as_bytes())
         .with_context(|| "Failed to sign token")?;
     let token_bytes = token.to_bytes().with_context(|| "Failed to encode token")?;
     let token_str = base64_url::encode(&token_bytes);
@@ -1,11 +1,21 @@
-use std::collections::HashMap;
+use std::{collections::BTreeMap, rc::Rc};
 
+use anyhow::Result;
 use garde::Validate;
 use serde::Deserialize;
 
+use crate::api::tenancy::hash_key;
+use crate::geoip::ClientLocation;
+use crate::minting::TokenClaims;
+use crate::persistence::{
+    BlockedClaimType, ClaimCondition, ClaimPolicy, ClaimTemplate, IssuerKey, IssuerPolicy,
+    RateLimit, RateLimitKey, RequestField, SessionPolicy, Settings, Tenant, ValidationProfile,
+};
 use crate::validator::{
-    CatCountryValidator, CatHeaderValidator, CatNipValidator, CatValidationOptions,
-    CatVersionValidator,
+    is_built_in_claim, AsyncValidate, CatAlpnValidator, CatCountryValidator, CatGeoAltValidator,
+    CatGeoCoordValidator, CatGeohashValidator, CatHeaderValidator, CatMethodValidator,
+    CatNipValidator, CatUriValidator, CatValidationOptions, CatVersionValidator, Coordinate,
+    CustomClaimValidator, Headers, RateLimitValidator, TimeValidationOptions, ValidatorRegistry,
 };
 
 #[derive(Deserialize)]
@@ -13,6 +23,224 @@ pub struct ItemsModel<T> {
     pub values: Vec<T>,
 }
 
+#[derive(Deserialize)]
+pub struct RevokeTokenModel {
+    // base64url encoded CWT ID (cti) of the token
+    pub cti: String,
+    // expiration (exp) of the token, the revocation is dropped afterwards
+    pub exp: Option<u64>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct IssuerPolicyModel {
+    #[garde(length(min = 1))]
+    pub issuer: String,
+    #[garde(length(min = 1))]
+    pub keys: Vec<IssuerKeyModel>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    #[garde(range(min = 1))]
+    pub max_token_lifetime: Option<u64>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub required_claims: Vec<i32>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub clock_skew: u64,
+    #[garde(skip)]
+    pub exp_leeway: Option<u64>,
+    #[garde(skip)]
+    pub nbf_leeway: Option<u64>,
+    #[garde(skip)]
+    pub iat_leeway: Option<u64>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub require_expiration: bool,
+    #[garde(skip)]
+    pub active_kid: Option<String>,
+    #[garde(length(min = 16))]
+    pub minting_key: Option<String>,
+}
+
+impl IssuerPolicyModel {
+    // the active key has to be part of the keyring
+    pub fn has_active_key(&self) -> bool {
+        self.active_kid
+            .as_ref()
+            .map_or(true, |kid| self.keys.iter().any(|key| &key.kid == kid))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IssuerKeyModel {
+    pub kid: String,
+    pub secret: String,
+}
+
+impl From<IssuerPolicyModel> for IssuerPolicy {
+    fn from(value: IssuerPolicyModel) -> Self {
+        Self {
+            issuer: value.issuer,
+            keys: value
+                .keys
+                .into_iter()
+                .map(|key| IssuerKey {
+                    kid: key.kid,
+                    secret: key.secret,
+                })
+                .collect(),
+            audiences: value.audiences,
+            max_token_lifetime: value.max_token_lifetime,
+            required_claims: value.required_claims,
+            clock_skew: value.clock_skew,
+            exp_leeway: value.exp_leeway,
+            nbf_leeway: value.nbf_leeway,
+            iat_leeway: value.iat_leeway,
+            require_expiration: value.require_expiration,
+            active_kid: value.active_kid,
+            minting_key_hash: value.minting_key.as_deref().map(hash_key),
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ValidationProfileModel {
+    #[garde(length(min = 1, max = 64), custom(is_profile_name))]
+    pub name: String,
+    #[garde(skip)]
+    #[serde(default)]
+    pub claims: BTreeMap<i32, ClaimPolicy>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub revocation: bool,
+    #[garde(skip)]
+    #[serde(default)]
+    pub blocklists: Vec<BlockedClaimType>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub replay_protection: bool,
+    #[garde(skip)]
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    #[garde(skip)]
+    pub exp_leeway: Option<u64>,
+    #[garde(skip)]
+    pub nbf_leeway: Option<u64>,
+    #[garde(skip)]
+    pub iat_leeway: Option<u64>,
+    #[garde(range(min = 1))]
+    pub max_token_lifetime: Option<u64>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub require_expiration: bool,
+    #[garde(dive)]
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimitModel>,
+    #[garde(dive)]
+    pub sessions: Option<SessionPolicyModel>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RateLimitModel {
+    #[garde(skip)]
+    pub key: RateLimitKey,
+    #[garde(range(min = 1))]
+    pub limit: u64,
+    #[garde(range(min = 1))]
+    pub window: u64,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct SessionPolicyModel {
+    #[garde(range(min = 1))]
+    pub max_sessions: u32,
+    #[garde(range(min = 1))]
+    pub ttl: u64,
+    #[garde(skip)]
+    pub session_header: Option<String>,
+}
+
+// profile names are used as path segment of /validate/:profile, simple is taken
+fn is_profile_name(value: &str, _: &()) -> garde::Result {
+    if value == "simple"
+        || !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(garde::Error::new(
+            "must consist of letters, digits, - and _ and must not be simple",
+        ));
+    }
+    Ok(())
+}
+
+impl From<ValidationProfileModel> for ValidationProfile {
+    fn from(value: ValidationProfileModel) -> Self {
+        Self {
+            name: value.name,
+            claims: value.claims,
+            revocation: value.revocation,
+            blocklists: value.blocklists,
+            replay_protection: value.replay_protection,
+            audiences: value.audiences,
+            exp_leeway: value.exp_leeway,
+            nbf_leeway: value.nbf_leeway,
+            iat_leeway: value.iat_leeway,
+            max_token_lifetime: value.max_token_lifetime,
+            require_expiration: value.require_expiration,
+            rate_limits: value
+                .rate_limits
+                .into_iter()
+                .map(|limit| RateLimit {
+                    key: limit.key,
+                    limit: limit.limit,
+                    window: limit.window,
+                })
+                .collect(),
+            sessions: value.sessions.map(|policy| SessionPolicy {
+                max_sessions: policy.max_sessions,
+                ttl: policy.ttl,
+                session_header: policy.session_header,
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct TenantModel {
+    #[garde(length(min = 1, max = 64), custom(is_tenant_id))]
+    pub id: String,
+    #[garde(skip)]
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[garde(length(min = 16))]
+    pub admin_key: String,
+}
+
+// tenant ids are used as path segment of /tenants/:tenant and in key-value store keys
+fn is_tenant_id(value: &str, _: &()) -> garde::Result {
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(garde::Error::new(
+            "must consist of lowercase letters, digits and -",
+        ));
+    }
+    Ok(())
+}
+
+impl From<TenantModel> for Tenant {
+    fn from(value: TenantModel) -> Self {
+        Self {
+            id: value.id,
+            hosts: value.hosts,
+            admin_key_hash: hash_key(&value.admin_key),
+        }
+    }
+}
+
 #[derive(Deserialize, Validate)]
 pub struct GenerateTokenRequestModel {
     #[garde(skip)]
@@ -28,6 +256,79 @@ pub struct GenerateTokenRequestModel {
     pub expiration_in_hours: u64,
     #[garde(skip)]
     pub token_identifier: String,
+    // ALPN protocols (h2, h3, http/1.1) the token may be used with
+    #[garde(skip)]
+    pub alpn: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ClaimTemplateModel {
+    #[garde(length(min = 1, max = 64))]
+    pub name: String,
+    #[garde(skip)]
+    pub audience: Option<String>,
+    #[garde(range(min = 1))]
+    pub lifetime: u64,
+    #[garde(url)]
+    pub url_prefix: Option<String>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub methods: Vec<String>,
+    // ISO 3166 conform country or region codes
+    #[garde(skip)]
+    #[serde(default)]
+    pub countries: Vec<String>,
+    #[garde(skip)]
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+impl From<ClaimTemplateModel> for ClaimTemplate {
+    fn from(value: ClaimTemplateModel) -> Self {
+        Self {
+            name: value.name,
+            audience: value.audience,
+            lifetime: value.lifetime,
+            url_prefix: value.url_prefix,
+            methods: value.methods,
+            countries: value.countries,
+            alpn: value.alpn,
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct MintTokenRequestModel {
+    #[garde(length(min = 1))]
+    pub issuer: String,
+    #[garde(length(min = 1))]
+    pub template: String,
+    // overrides of the template
+    #[garde(skip)]
+    pub subject: Option<String>,
+    #[garde(length(min = 1))]
+    pub countries: Option<Vec<String>>,
+    #[garde(url)]
+    pub url_prefix: Option<String>,
+    #[garde(range(min = 1))]
+    pub lifetime: Option<u64>,
+}
+
+impl MintTokenRequestModel {
+    // the cti is always generated, so replay and revocation checks can rely on it being unique
+    pub fn into_token_claims(self, template: &ClaimTemplate, cti: Vec<u8>) -> TokenClaims {
+        TokenClaims {
+            issuer: self.issuer,
+            subject: self.subject,
+            audience: template.audience.clone(),
+            lifetime: self.lifetime.unwrap_or(template.lifetime),
+            url_prefix: self.url_prefix.or_else(|| template.url_prefix.clone()),
+            methods: template.methods.clone(),
+            countries: self.countries.unwrap_or_else(|| template.countries.clone()),
+            alpn: template.alpn.clone(),
+            cti,
+        }
+    }
 }
 
 #[derive(Deserialize, Validate)]
@@ -38,10 +339,8 @@ pub struct ValidateTokenRequestModel {
     pub url: String,
     #[garde(skip)]
     pub method: String,
-    #[garde(skip)]
-    pub issuer: String,
     #[garde(length(min = 1))]
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
     #[garde(skip)]
     #[serde(default = "validate_by_default")]
     pub validate_not_before: Option<bool>,
@@ -54,80 +353,144 @@ pub struct ValidateTokenRequestModel {
     pub client_ip: String,
     #[garde(skip)]
     pub country: Option<String>,
+    // ALPN protocol negotiated with the client (e.g. h2, h3, http/1.1)
+    #[garde(skip)]
+    pub alpn: Option<String>,
+    // client location in decimal degrees and altitude in meters
+    #[garde(range(min = -90.0, max = 90.0))]
+    pub latitude: Option<f64>,
+    #[garde(range(min = -180.0, max = 180.0))]
+    pub longitude: Option<f64>,
+    #[garde(skip)]
+    pub altitude: Option<f64>,
+    // nonce handed out to the client which its DPoP proof has to carry
+    #[garde(skip)]
+    pub dpop_nonce: Option<String>,
 }
 
 impl ValidateTokenRequestModel {
-    pub fn into_non_kv_validation_options(self) -> CatValidationOptions {
-        let user_agent = self
+    fn location(&self) -> Option<Coordinate> {
+        Some(Coordinate {
+            latitude: self.latitude?,
+            longitude: self.longitude?,
+        })
+    }
+
+    fn field_value(
+        &self,
+        field: &RequestField,
+        location: &ClientLocation,
+    ) -> Result<Option<String>> {
+        Ok(match field {
+            RequestField::Method => Some(self.method.clone()),
+            RequestField::Url => Some(self.url.clone()),
+            RequestField::ClientIp => Some(self.client_ip.clone()),
+            RequestField::Country => location.country()?.map(String::from),
+            RequestField::Alpn => self.alpn.clone(),
+            RequestField::Header(name) => self.headers.get(name).map(String::from),
+        })
+    }
+}
+
+impl ValidateTokenRequestModel {
+    pub fn into_validation_options(
+        self,
+        settings: &Settings,
+        profile: &ValidationProfile,
+        client_location: Rc<ClientLocation>,
+    ) -> Result<CatValidationOptions> {
+        let location = self.location();
+        // OPTIONS requests are CORS preflights only if they carry an Origin
+        let preflight_method = self
             .headers
-            .iter()
-            .find(|k| k.0.eq_ignore_ascii_case("User-Agent"))
-            .map(|found| found.1.clone());
-        let mut opts = CatValidationOptions {
+            .get("Origin")
+            .and(self.headers.get("Access-Control-Request-Method"))
+            .map(String::from);
+
+        let mut registry = ValidatorRegistry::default();
+        registry.register(Box::new(CatVersionValidator {}));
+        registry.register(Box::new(CatUriValidator {
             url: self.url.clone(),
-            validate_expiration: self.validate_expiration.unwrap_or(true),
-            validate_not_before: self.validate_not_before.unwrap_or(true),
-            audience: self.audience.clone(),
-            skip_kv_validations: true,
-            country: None,
+        }));
+        registry.register(Box::new(CatMethodValidator {
             method: self.method.clone(),
-            issuer: self.issuer.clone(),
+            preflight_method,
+            head_implied_by_get: settings.head_implied_by_get,
+            allow_preflight: settings.allow_preflight,
+        }));
+        registry.register(Box::new(CatHeaderValidator {
+            headers: self.headers.clone(),
+            match_mode: settings.header_match,
+        }));
+        registry.register(Box::new(CatNipValidator {
             client_ip: self.client_ip.clone(),
-            user_agent,
-            sync_validators: vec![
-                Box::new(CatVersionValidator {}),
-                Box::new(CatHeaderValidator {
-                    headers: self.headers,
-                }),
-                Box::new(CatNipValidator {
-                    client_ip: self.client_ip.clone(),
-                }),
-            ],
-        };
-        if self.country.is_some() {
-            opts.sync_validators.push(Box::new(CatCountryValidator {
-                country: self.country.unwrap(),
-            }))
-        }
-        opts
-    }
-}
+        }));
+        registry.register(Box::new(CatAlpnValidator {
+            alpn: self.alpn.clone(),
+        }));
+        registry.register(Box::new(CatGeoCoordValidator { location }));
+        registry.register(Box::new(CatGeohashValidator { location }));
+        registry.register(Box::new(CatGeoAltValidator {
+            altitude: self.altitude,
+        }));
+        registry.register(Box::new(CatCountryValidator {
+            location: client_location.clone(),
+            unknown_location: settings.unknown_location,
+        }));
 
-impl From<ValidateTokenRequestModel> for CatValidationOptions {
-    fn from(value: ValidateTokenRequestModel) -> Self {
-        let user_agent = value
-            .headers
+        // custom claim rules only apply to private claims, they never replace built-in validators
+        for (claim_key, rule) in settings
+            .custom_claims
             .iter()
-            .find(|k| k.0.eq_ignore_ascii_case("User-Agent"))
-            .map(|found| found.1.clone());
-        let mut opts = Self {
-            url: value.url.clone(),
-            validate_expiration: value.validate_expiration.unwrap_or(true),
-            validate_not_before: value.validate_not_before.unwrap_or(true),
-            skip_kv_validations: false,
-            country: value.country.clone(),
-            audience: value.audience.clone(),
-            method: value.method.clone(),
-            client_ip: value.client_ip.clone(),
-            user_agent,
-            issuer: value.issuer.clone(),
-            sync_validators: vec![
-                Box::new(CatVersionValidator {}),
-                Box::new(CatHeaderValidator {
-                    headers: value.headers,
-                }),
-                Box::new(CatNipValidator {
-                    client_ip: value.client_ip,
-                }),
-            ],
-        };
-
-        if value.country.is_some() {
-            opts.sync_validators.push(Box::new(CatCountryValidator {
-                country: value.country.unwrap(),
-            }))
+            .filter(|(claim_key, _)| !is_built_in_claim(**claim_key))
+        {
+            let field_value = match &rule.condition {
+                Some(ClaimCondition::Compare { field, .. }) => {
+                    self.field_value(field, &client_location)?
+                }
+                _ => None,
+            };
+            registry.register(Box::new(CustomClaimValidator {
+                claim_key: *claim_key,
+                rule: rule.clone(),
+                field_value,
+            }));
         }
-        opts
+
+        let mut async_validators: Vec<Box<dyn AsyncValidate>> = vec![];
+        if !profile.rate_limits.is_empty() {
+            async_validators.push(Box::new(RateLimitValidator {
+                profile: profile.name.clone(),
+                limits: profile.rate_limits.clone(),
+            }));
+        }
+
+        Ok(CatValidationOptions {
+            url: self.url,
+            time: TimeValidationOptions {
+                validate_expiration: self.validate_expiration.unwrap_or(true),
+                validate_not_before: self.validate_not_before.unwrap_or(true),
+                exp_leeway: profile.exp_leeway,
+                nbf_leeway: profile.nbf_leeway,
+                iat_leeway: profile.iat_leeway,
+                max_token_lifetime: profile.max_token_lifetime,
+                require_expiration: profile.require_expiration,
+            },
+            audience: self.audience,
+            audiences: profile.audiences.clone(),
+            revocation: profile.revocation,
+            blocklists: profile.blocklists.clone(),
+            replay_protection: profile.replay_protection,
+            sessions: profile.sessions.clone(),
+            location: client_location,
+            unknown_location: settings.unknown_location,
+            method: self.method,
+            client_ip: self.client_ip,
+            headers: self.headers,
+            proof_nonce: self.dpop_nonce,
+            sync_validators: registry.build(&profile.claims),
+            async_validators,
+        })
     }
 }
 
@@ -0,0 +1,106 @@
+use sha2::{Digest, Sha256};
+use spin_sdk::http::{Params, Request, Response};
+use subtle::ConstantTimeEq;
+
+use crate::persistence::{Persistence, Tenant};
+
+const TENANT_HEADER: &str = "x-tenant-id";
+// key of the operator, authorizing the admin API of the default scope and of every tenant
+const VARIABLE_ADMIN_KEY: &str = "admin_key";
+
+// the tenant is taken from the route (/tenants/:tenant/...) or the host of the request,
+// requests without a tenant use the default scope
+pub fn resolve(req: &Request, p: &Params) -> Result<Persistence, Response> {
+    Ok(match resolve_tenant(req, p, false)? {
+        Some(tenant) => Persistence::for_tenant(&tenant.id),
+        None => Persistence::default_scope(),
+    })
+}
+
+// the default scope is managed by the operator, tenants manage their own data only, authorized
+// by their admin key (Authorization: Bearer)
+pub fn resolve_admin(req: &Request, p: &Params) -> Result<Persistence, Response> {
+    // the x-tenant-id header is trusted as the request has to present a key of the tenant
+    let tenant = resolve_tenant(req, p, true)?;
+    let authorized = is_operator(req)
+        || tenant.as_ref().is_some_and(|tenant| {
+            bearer(req).is_some_and(|key| key_matches(key, &tenant.admin_key_hash))
+        });
+    if !authorized {
+        return Err(Response::new(401, "Unauthorized"));
+    }
+    Ok(match tenant {
+        Some(tenant) => Persistence::for_tenant(&tenant.id),
+        None => Persistence::default_scope(),
+    })
+}
+
+// the tenant registry, the GeoIP database, metrics and test tokens are shared by the
+// deployment and only available to the operator
+pub fn require_operator(req: &Request, p: &Params) -> Result<(), Response> {
+    if resolve_tenant(req, p, true)?.is_some() {
+        return Err(Response::new(403, "Forbidden"));
+    }
+    match is_operator(req) {
+        true => Ok(()),
+        false => Err(Response::new(401, "Unauthorized")),
+    }
+}
+
+// credential of an Authorization: Bearer header
+pub fn bearer(req: &Request) -> Option<&str> {
+    req.header("authorization")
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// compares a presented key with a stored hash in constant time
+pub fn key_matches(key: &str, hash: &str) -> bool {
+    hash_key(key).as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+// nobody is the operator unless the admin_key variable is set
+fn is_operator(req: &Request) -> bool {
+    let Some(admin_key) = spin_sdk::variables::get(VARIABLE_ADMIN_KEY)
+        .ok()
+        .filter(|key| !key.is_empty())
+    else {
+        return false;
+    };
+    bearer(req).is_some_and(|key| key_matches(key, &hash_key(&admin_key)))
+}
+
+// the x-tenant-id header is set by clients, it's only taken into account if trust_header is set
+fn resolve_tenant(
+    req: &Request,
+    p: &Params,
+    trust_header: bool,
+) -> Result<Option<Tenant>, Response> {
+    let requested = p.get("tenant").or_else(|| {
+        req.header(TENANT_HEADER)
+            .filter(|_| trust_header)
+            .and_then(|value| value.as_str())
+    });
+    if let Some(id) = requested {
+        return match Persistence::find_tenant(id) {
+            Ok(Some(tenant)) => Ok(Some(tenant)),
+            Ok(None) => Err(Response::new(404, "Tenant not found")),
+            Err(_) => Err(Response::new(500, ())),
+        };
+    }
+    let Some(host) = req
+        .header("host")
+        .and_then(|value| value.as_str())
+        .and_then(|host| host.split(':').next())
+    else {
+        return Ok(None);
+    };
+    Persistence::find_tenant_by_host(host).map_err(|_| Response::new(500, ()))
+}
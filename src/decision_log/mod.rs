@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use common_access_token::current_timestamp;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{persistence::DecisionLogSettings, validator::Observation};
+
+// key of the HMAC pseudonymizing subjects, subjects aren't logged at all if hash_subject is set
+// without a key
+const VARIABLE_SECRET: &str = "decision_log_secret";
+
+/// Request attributes logged along with a decision
+pub struct Decision<'a> {
+    pub tenant: Option<&'a str>,
+    pub profile: &'a str,
+    // unknown for malformed requests
+    pub client_ip: Option<&'a str>,
+    pub country: Option<&'a str>,
+    pub duration: Duration,
+}
+
+// writes one JSON line per (sampled) decision to stdout, the token itself is never logged
+pub fn log(settings: &DecisionLogSettings, decision: &Decision, observation: &Observation) {
+    let rate = match observation.failure {
+        None => settings.success_sample_rate,
+        Some(_) => settings.failure_sample_rate,
+    };
+    if !sampled(rate) {
+        return;
+    }
+    let subject = observation
+        .subject
+        .as_deref()
+        .and_then(|sub| match settings.hash_subject {
+            true => pseudonym(sub),
+            false => Some(sub.to_string()),
+        });
+    let line = json!({
+        "timestamp": current_timestamp(),
+        "tenant": decision.tenant,
+        "profile": decision.profile,
+        "outcome": match observation.failure {
+            None => "success",
+            Some(_) => "failure",
+        },
+        "code": observation.failure.map(|code| code.as_str()),
+        "claim": observation.claim,
+        "iss": observation.issuer,
+        "sub": subject,
+        "cti": observation.cti,
+        "replayed": observation.replayed,
+        "client_ip": decision.client_ip,
+        "country": decision.country,
+        "duration_ms": decision.duration.as_secs_f64() * 1000.0,
+    });
+    println!("{line}");
+}
+
+// an unsalted hash of a subject is easily reversed by hashing candidate subjects, the HMAC
+// can't be computed without the secret
+fn pseudonym(subject: &str) -> Option<String> {
+    let secret = spin_sdk::variables::get(VARIABLE_SECRET)
+        .ok()
+        .filter(|secret| !secret.is_empty())?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(subject.as_bytes());
+    Some(
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    )
+}
+
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    let mut bytes = [0u8; 8];
+    // decisions are logged if no randomness is available
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return true;
+    }
+    (u64::from_le_bytes(bytes) as f64 / u64::MAX as f64) < rate
+}
@@ -0,0 +1,68 @@
+use std::{cell::OnceCell, net::IpAddr};
+
+use anyhow::{Context, Result};
+use maxminddb::{path, Reader};
+
+use crate::persistence::Persistence;
+
+/// Location of the client, looked up in the offline GeoIP database on first use unless the
+/// caller provided the country
+pub struct ClientLocation {
+    client_ip: String,
+    country: OnceCell<Option<String>>,
+}
+
+impl ClientLocation {
+    pub fn new(client_ip: String, country: Option<String>) -> Self {
+        let cell = OnceCell::new();
+        if country.is_some() {
+            _ = cell.set(country);
+        }
+        Self {
+            client_ip,
+            country: cell,
+        }
+    }
+
+    // lookup errors (e.g. a corrupt database) are returned, so validations fail closed
+    pub fn country(&self) -> Result<Option<&str>> {
+        if let Some(country) = self.country.get() {
+            return Ok(country.as_deref());
+        }
+        let resolved = resolve_country(&self.client_ip)?;
+        Ok(self.country.get_or_init(|| resolved).as_deref())
+    }
+
+    // the country if it was provided or has been looked up, never triggers a lookup
+    pub fn known_country(&self) -> Option<&str> {
+        self.country.get().and_then(|country| country.as_deref())
+    }
+}
+
+// resolves the ISO 3166-1 alpha-2 country code of an IP address using the offline GeoIP database,
+// narrowed down to the ISO 3166-2 subdivision (e.g. US-CA) if the database provides one
+fn resolve_country(client_ip: &str) -> Result<Option<String>> {
+    let Some(database) = Persistence::get_geoip_database()? else {
+        return Ok(None);
+    };
+    let ip: IpAddr = client_ip
+        .parse()
+        .with_context(|| "Invalid IP address received")?;
+    let reader = Reader::from_source(database).with_context(|| "Invalid GeoIP database")?;
+    let result = reader.lookup(ip)?;
+    let Some(country) = result.decode_path::<String>(&path!["country", "iso_code"])? else {
+        return Ok(None);
+    };
+    // only City databases carry subdivisions
+    Ok(
+        match result.decode_path::<String>(&path!["subdivisions", 0, "iso_code"])? {
+            Some(subdivision) => Some(format!("{country}-{subdivision}")),
+            None => Some(country),
+        },
+    )
+}
+
+pub(super) fn verify_database(database: &[u8]) -> Result<()> {
+    Reader::from_source(database).with_context(|| "Invalid GeoIP database")?;
+    Ok(())
+}
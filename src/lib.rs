@@ -2,30 +2,89 @@ use spin_sdk::http::{IntoResponse, Request, Router};
 use spin_sdk::http_component;
 
 use crate::api::handlers::{
-    add_asns_to_blocklist, add_items_to_blocklist, generate_test_token, get_blocking_data,
-    remove_asns_from_blocklist, remove_items_from_blocklist, validate_token, validate_token_simple,
+    add_asns_to_blocklist, add_items_to_blocklist, generate_test_token, get_audit_log,
+    get_blocking_data, get_issuers, get_metrics, get_profiles, get_revoked_tokens, get_sessions,
+    get_settings, get_templates, get_tenants, kick_sessions, mint_token,
+    remove_asns_from_blocklist, remove_geoip_database, remove_issuers, remove_items_from_blocklist,
+    remove_profiles, remove_templates, remove_tenants, revoke_tokens, set_geoip_database,
+    set_settings, unrevoke_tokens, upsert_issuer, upsert_profile, upsert_template, upsert_tenant,
+    validate_token, validate_token_simple, validate_token_with_profile,
 };
 
 mod api;
 mod asn_resolver;
+mod decision_log;
+mod geoip;
+mod metrics;
+mod minting;
 mod persistence;
+mod telemetry;
 mod validator;
 
 #[http_component]
 fn handle_cat_validator(req: Request) -> anyhow::Result<impl IntoResponse> {
     let mut router = Router::default();
-    router.post_async("/validate/simple", validate_token_simple);
-    router.post_async("/validate", validate_token);
-    router.post("/api/tests/tokens", generate_test_token);
-    router.post("/api/blocking-data/simple/:kind", add_items_to_blocklist);
-    router.delete(
-        "/api/blocking-data/simple/:kind",
-        remove_items_from_blocklist,
-    );
-
-    router.post_async("/api/blocking-data/asns", add_asns_to_blocklist);
-    router.delete("/api/blocking-data/asns", remove_asns_from_blocklist);
-
-    router.get("/api/blocking-data", get_blocking_data);
+    // tenant scoped routes are available with and without the tenant in the path
+    for prefix in ["", "/tenants/:tenant"] {
+        router.post_async(&format!("{prefix}/validate/simple"), validate_token_simple);
+        router.post_async(&format!("{prefix}/validate"), validate_token);
+        router.post_async(
+            &format!("{prefix}/validate/:profile"),
+            validate_token_with_profile,
+        );
+        router.post(&format!("{prefix}/api/tests/tokens"), generate_test_token);
+        router.post(&format!("{prefix}/api/tokens"), mint_token);
+        router.get(&format!("{prefix}/api/templates"), get_templates);
+        router.post(&format!("{prefix}/api/templates"), upsert_template);
+        router.delete(&format!("{prefix}/api/templates"), remove_templates);
+        router.post(
+            &format!("{prefix}/api/blocking-data/simple/:kind"),
+            add_items_to_blocklist,
+        );
+        router.delete(
+            &format!("{prefix}/api/blocking-data/simple/:kind"),
+            remove_items_from_blocklist,
+        );
+
+        router.post_async(
+            &format!("{prefix}/api/blocking-data/asns"),
+            add_asns_to_blocklist,
+        );
+        router.delete(
+            &format!("{prefix}/api/blocking-data/asns"),
+            remove_asns_from_blocklist,
+        );
+
+        router.get(&format!("{prefix}/api/blocking-data"), get_blocking_data);
+
+        router.get(&format!("{prefix}/api/revocations"), get_revoked_tokens);
+        router.post(&format!("{prefix}/api/revocations"), revoke_tokens);
+        router.delete(&format!("{prefix}/api/revocations"), unrevoke_tokens);
+
+        router.get(&format!("{prefix}/api/issuers"), get_issuers);
+        router.post(&format!("{prefix}/api/issuers"), upsert_issuer);
+        router.delete(&format!("{prefix}/api/issuers"), remove_issuers);
+
+        router.get(&format!("{prefix}/api/profiles"), get_profiles);
+        router.post(&format!("{prefix}/api/profiles"), upsert_profile);
+        router.delete(&format!("{prefix}/api/profiles"), remove_profiles);
+
+        router.get(&format!("{prefix}/api/settings"), get_settings);
+        router.put(&format!("{prefix}/api/settings"), set_settings);
+
+        router.get(&format!("{prefix}/api/sessions/*"), get_sessions);
+        router.delete(&format!("{prefix}/api/sessions/*"), kick_sessions);
+
+        router.get(&format!("{prefix}/api/audit"), get_audit_log);
+    }
+
+    router.get("/api/tenants", get_tenants);
+    router.post("/api/tenants", upsert_tenant);
+    router.delete("/api/tenants", remove_tenants);
+
+    router.get("/metrics", get_metrics);
+
+    router.put("/api/geoip", set_geoip_database);
+    router.delete("/api/geoip", remove_geoip_database);
     Ok(router.handle(req))
 }
@@ -0,0 +1,177 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use crate::{
+    persistence::{BlockedClaimType, Metrics, Persistence},
+    validator::{FailureCode, Observation},
+};
+
+const VALIDATIONS: &str = "cat_validations_total";
+const BLOCKLIST_HITS: &str = "cat_blocklist_hits_total";
+const SIGNATURE_FAILURES: &str = "cat_signature_failures_total";
+const STAGE_DURATION: &str = "cat_stage_duration_seconds";
+
+// upper bounds of the stage duration buckets in microseconds
+const STAGE_BUCKETS: [u64; 10] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 250_000,
+];
+
+const HELP: [(&str, &str); 4] = [
+    (VALIDATIONS, "Token validations by outcome and failure code"),
+    (
+        BLOCKLIST_HITS,
+        "Requests rejected by a blocklist, by category",
+    ),
+    (
+        SIGNATURE_FAILURES,
+        "Failed signature verifications by key id",
+    ),
+    (STAGE_DURATION, "Duration of the validation pipeline stages"),
+];
+
+// adds a validation to the aggregate in the key-value store
+pub fn record(observation: &Observation) -> Result<()> {
+    Persistence::update_metrics(|metrics| {
+        let (outcome, code) = match observation.failure {
+            None => ("success", "none"),
+            Some(code) => ("failure", code.as_str()),
+        };
+        metrics.increment(VALIDATIONS, labels(&[("outcome", outcome), ("code", code)]));
+        if let Some(category) = observation.blocked {
+            metrics.increment(
+                BLOCKLIST_HITS,
+                labels(&[("category", category_name(category))]),
+            );
+        }
+        if observation.failure == Some(FailureCode::InvalidSignature) {
+            let kid = observation.kid.as_deref().unwrap_or("none");
+            metrics.increment(SIGNATURE_FAILURES, labels(&[("kid", kid)]));
+        }
+        for (stage, duration) in &observation.stages {
+            metrics.observe(
+                STAGE_DURATION,
+                labels(&[("stage", stage.as_str())]),
+                &STAGE_BUCKETS,
+                duration.as_micros() as u64,
+            );
+        }
+    })
+}
+
+// Prometheus text exposition format
+pub fn render(metrics: &Metrics) -> String {
+    let mut out = String::new();
+    for (name, help) in HELP {
+        let (counters, histograms) = (metrics.counters.get(name), metrics.histograms.get(name));
+        if counters.is_none() && histograms.is_none() {
+            continue;
+        }
+        let _ = writeln!(out, "# HELP {name} {help}");
+        if let Some(series) = counters {
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (labels, value) in series {
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+        }
+        if let Some(series) = histograms {
+            let _ = writeln!(out, "# TYPE {name} histogram");
+            for (labels, histogram) in series {
+                for (bound, count) in STAGE_BUCKETS.iter().zip(&histogram.buckets) {
+                    let le = *bound as f64 / 1_000_000.0;
+                    let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{le}\"}} {count}");
+                }
+                let count = histogram.count;
+                let sum = histogram.sum_micros as f64 / 1_000_000.0;
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+                let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}");
+                let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+            }
+        }
+    }
+    out
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn category_name(category: BlockedClaimType) -> &'static str {
+    match category {
+        BlockedClaimType::Subject => "subject",
+        BlockedClaimType::Country => "country",
+        BlockedClaimType::Cidr => "cidr",
+        BlockedClaimType::UserAgent => "useragent",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters() {
+        let mut metrics = Metrics::default();
+        let success = labels(&[("outcome", "success"), ("code", "none")]);
+        metrics.increment(VALIDATIONS, success.clone());
+        metrics.increment(VALIDATIONS, success);
+        let out = render(&metrics);
+        assert!(out.contains("# TYPE cat_validations_total counter\n"));
+        assert!(out.contains("cat_validations_total{outcome=\"success\",code=\"none\"} 2\n"));
+        assert!(!out.contains(STAGE_DURATION));
+    }
+
+    #[test]
+    fn renders_cumulative_histograms() {
+        let mut metrics = Metrics::default();
+        let stage = labels(&[("stage", "verify")]);
+        metrics.observe(STAGE_DURATION, stage.clone(), &STAGE_BUCKETS, 80);
+        metrics.observe(STAGE_DURATION, stage, &STAGE_BUCKETS, 300);
+        let out = render(&metrics);
+        assert!(out.contains("# TYPE cat_stage_duration_seconds histogram\n"));
+        assert!(
+            out.contains("cat_stage_duration_seconds_bucket{stage=\"verify\",le=\"0.00005\"} 0\n")
+        );
+        assert!(
+            out.contains("cat_stage_duration_seconds_bucket{stage=\"verify\",le=\"0.0001\"} 1\n")
+        );
+        assert!(
+            out.contains("cat_stage_duration_seconds_bucket{stage=\"verify\",le=\"0.0005\"} 2\n")
+        );
+        assert!(out.contains("cat_stage_duration_seconds_bucket{stage=\"verify\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("cat_stage_duration_seconds_sum{stage=\"verify\"} 0.00038\n"));
+        assert!(out.contains("cat_stage_duration_seconds_count{stage=\"verify\"} 2\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(labels(&[("kid", "a\"b\\c\nd")]), "kid=\"a\\\"b\\\\c\\nd\"");
+    }
+
+    #[test]
+    fn merges_shards() {
+        let (mut all, mut shard) = (Metrics::default(), Metrics::default());
+        all.increment(VALIDATIONS, String::from("outcome=\"success\""));
+        shard.increment(VALIDATIONS, String::from("outcome=\"success\""));
+        shard.observe(
+            STAGE_DURATION,
+            String::from("stage=\"decode\""),
+            &STAGE_BUCKETS,
+            10,
+        );
+        all.merge(shard);
+        let out = render(&all);
+        assert!(out.contains("cat_validations_total{outcome=\"success\"} 2\n"));
+        assert!(out.contains("cat_stage_duration_seconds_count{stage=\"decode\"} 1\n"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Error, Result};
+use common_access_token::{
+    cat_keys, catm, catu, catv, current_timestamp, uri_components, Algorithm, CborValue, Claims,
+    KeyId, RegisteredClaims, Token, TokenBuilder,
+};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use crate::persistence::IssuerKey;
+
+/// Claims of a token to mint, merged from a claim template and the request
+pub struct TokenClaims {
+    pub issuer: String,
+    pub subject: Option<String>,
+    pub audience: Option<String>,
+    pub lifetime: u64,
+    pub url_prefix: Option<String>,
+    pub methods: Vec<String>,
+    pub countries: Vec<String>,
+    pub alpn: Vec<String>,
+    pub cti: Vec<u8>,
+}
+
+// signs the claims with the key of the issuer (HMAC SHA-256)
+pub fn mint(key: &IssuerKey, claims: &TokenClaims) -> Result<Token> {
+    let now = current_timestamp();
+    let mut registered = RegisteredClaims::new()
+        .with_issuer(claims.issuer.clone())
+        .with_expiration(now + claims.lifetime)
+        .with_not_before(now)
+        .with_issued_at(now)
+        .with_cti(claims.cti.clone());
+    if let Some(subject) = &claims.subject {
+        registered = registered.with_subject(subject.clone());
+    }
+    if let Some(audience) = &claims.audience {
+        registered = registered.with_audience(audience.clone());
+    }
+    let mut builder = TokenBuilder::new()
+        .algorithm(Algorithm::HmacSha256)
+        .protected_key_id(KeyId::string(key.kid.clone()))
+        .registered_claims(registered)
+        .custom_cbor(cat_keys::CATV, catv::create());
+    if let Some(url_prefix) = &claims.url_prefix {
+        builder = builder.custom_cbor(cat_keys::CATU, catu::create(uri_prefix(url_prefix)?));
+    }
+    if !claims.methods.is_empty() {
+        builder = builder.custom_array(
+            cat_keys::CATM,
+            catm::create(claims.methods.iter().map(|m| m.as_str()).collect()),
+        );
+    }
+    if !claims.countries.is_empty() {
+        builder = builder.custom_array(
+            cat_keys::CATGEOISO3166,
+            claims
+                .countries
+                .iter()
+                .cloned()
+                .map(CborValue::Text)
+                .collect(),
+        );
+    }
+    if !claims.alpn.is_empty() {
+        builder = builder.custom_array(
+            cat_keys::CATALPN,
+            claims.alpn.iter().cloned().map(CborValue::Text).collect(),
+        );
+    }
+    builder
+        .sign(key.secret.as_bytes())
+        .with_context(|| "Failed to sign token")
+}
+
+pub fn random_cti() -> Result<Vec<u8>> {
+    let mut cti = vec![0u8; 16];
+    getrandom::getrandom(&mut cti)
+        .map_err(|e| Error::msg(format!("No randomness for cti: {e}")))?;
+    Ok(cti)
+}
+
+// catu components matching all URLs starting with the prefix
+fn uri_prefix(url_prefix: &str) -> Result<BTreeMap<i32, BTreeMap<i32, CborValue>>> {
+    let url = Url::parse(url_prefix).with_context(|| "Invalid URL prefix")?;
+    let Some(host) = url.host_str() else {
+        return Err(Error::msg("URL prefix without host"));
+    };
+    let mut components = BTreeMap::new();
+    components.insert(uri_components::SCHEME, catu::exact_match(url.scheme()));
+    components.insert(uri_components::HOST, catu::exact_match(host));
+    if let Some(port) = url.port() {
+        components.insert(uri_components::PORT, catu::exact_match(&port.to_string()));
+    }
+    if url.path() != "/" {
+        components.insert(uri_components::PATH, catu::prefix_match(url.path()));
+    }
+    Ok(components)
+}
+
+// JSON representation of the claims, custom claims are keyed by claim key and
+// byte strings are base64url encoded
+pub fn claims_json(claims: &Claims) -> Value {
+    let registered = &claims.registered;
+    let custom = claims
+        .custom
+        .iter()
+        .map(|(key, value)| (key.to_string(), cbor_json(value)))
+        .collect::<Map<_, _>>();
+    json!({
+        "iss": registered.iss,
+        "sub": registered.sub,
+        "aud": registered.aud,
+        "exp": registered.exp,
+        "nbf": registered.nbf,
+        "iat": registered.iat,
+        "cti": registered.cti.as_ref().map(base64_url::encode),
+        "custom": custom,
+    })
+}
+
+fn cbor_json(value: &CborValue) -> Value {
+    match value {
+        CborValue::Integer(value) => json!(value),
+        CborValue::Bytes(value) => json!(base64_url::encode(value)),
+        CborValue::Text(value) => json!(value),
+        CborValue::Map(value) => Value::Object(
+            value
+                .iter()
+                .map(|(key, value)| (key.to_string(), cbor_json(value)))
+                .collect(),
+        ),
+        CborValue::Array(values) => Value::Array(values.iter().map(cbor_json).collect()),
+        CborValue::Null => Value::Null,
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{Context, Error, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use spin_sdk::wit::wasi::keyvalue::{
+    atomics::{self, Cas, CasError},
+    store::{self, Bucket},
+};
+
+// the default store backs wasi:keyvalue as well as the Spin key-value API
+const STORE_DEFAULT: &str = "default";
+// compare-and-swap attempts before giving up on a contended key
+const MAX_SWAP_ATTEMPTS: usize = 16;
+
+fn bucket() -> Result<Bucket> {
+    store::open(STORE_DEFAULT).map_err(|e| Error::msg(format!("Error opening store ({e:?})")))
+}
+
+// adds delta to the counter stored at key, returns the new value
+pub fn increment(key: &str, delta: i64) -> Result<i64> {
+    atomics::increment(&bucket()?, key, delta)
+        .map_err(|e| Error::msg(format!("Error incrementing {key} ({e:?})")))
+}
+
+// read-modify-write of the JSON value stored at key using compare-and-swap, so concurrent
+// updates are never lost. The update returns the new value (None leaves the key untouched)
+// and a result which is handed back to the caller
+pub fn update_json<T, R>(
+    key: &str,
+    update: impl Fn(Option<T>) -> Result<(Option<T>, R)>,
+) -> Result<R>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let bucket = bucket()?;
+    let mut cas = Cas::new(&bucket, key).map_err(|e| Error::msg(format!("{e:?}")))?;
+    for _ in 0..MAX_SWAP_ATTEMPTS {
+        let current = cas
+            .current()
+            .map_err(|e| Error::msg(format!("{e:?}")))?
+            .map(|value| serde_json::from_slice::<T>(&value))
+            .transpose()
+            .with_context(|| format!("Error decoding {key}"))?;
+        let (value, result) = update(current)?;
+        let Some(value) = value else {
+            return Ok(result);
+        };
+        let value = serde_json::to_vec(&value).with_context(|| format!("Error encoding {key}"))?;
+        match atomics::swap(cas, &value) {
+            Ok(_) => return Ok(result),
+            Err(CasError::CasFailed(latest)) => cas = latest,
+            Err(CasError::StoreError(e)) => {
+                return Err(Error::msg(format!("Error storing {key} ({e:?})")))
+            }
+        }
+    }
+    Err(Error::msg(format!(
+        "Error storing {key} (too many concurrent updates)"
+    )))
+}
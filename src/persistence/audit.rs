@@ -0,0 +1,68 @@
+use anyhow::{Context, Result};
+use common_access_token::current_timestamp;
+use serde::{Deserialize, Serialize};
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::Persistence;
+
+const KEY_AUDIT: &str = "audit";
+// oldest entries are dropped once the log is full
+const MAX_AUDIT_ENTRIES: usize = 1000;
+
+impl Persistence {
+    pub fn get_audit_log(&self) -> Result<AuditLog> {
+        let store = Store::open_default()?;
+        Ok(match store.get_json::<AuditLog>(self.key(KEY_AUDIT))? {
+            None => AuditLog::new(),
+            Some(a) => a,
+        })
+    }
+
+    pub fn append_audit_entry(&self, action: &str, values: Vec<String>) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<AuditLog>(self.key(KEY_AUDIT))? {
+            Some(log) => log,
+            None => AuditLog::new(),
+        };
+        all.entries.push(AuditEntry {
+            timestamp: current_timestamp(),
+            action: action.to_string(),
+            values,
+        });
+        if all.entries.len() > MAX_AUDIT_ENTRIES {
+            let overflow = all.entries.len() - MAX_AUDIT_ENTRIES;
+            all.entries.drain(..overflow);
+        }
+        store
+            .set_json(self.key(KEY_AUDIT), &all)
+            .with_context(|| "Error storing audit log")
+    }
+}
+
+/// Changes made through the admin API, oldest first
+#[derive(Deserialize, Serialize)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub action: String,
+    // identifiers of the affected items (issuers, profiles, blocked values, ...)
+    pub values: Vec<String>,
+}
+
+impl AuditLog {
+    fn new() -> Self {
+        Self { entries: vec![] }
+    }
+}
+
+impl IntoBody for AuditLog {
+    fn into_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self)
+            .with_context(|| "Error serializing AuditLog")
+            .unwrap()
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::{Context, Result};
+use spin_sdk::key_value::Store;
+
+use crate::persistence::Persistence;
+
+const KEY_GEOIP_DATABASE: &str = "geoip";
+
+impl Persistence {
+    pub fn get_geoip_database() -> Result<Option<Vec<u8>>> {
+        let store = Store::open_default()?;
+        store
+            .get(KEY_GEOIP_DATABASE)
+            .with_context(|| "Error loading GeoIP database")
+    }
+
+    pub fn set_geoip_database(database: &[u8]) -> Result<()> {
+        let store = Store::open_default()?;
+        store
+            .set(KEY_GEOIP_DATABASE, database)
+            .with_context(|| "Error storing GeoIP database")
+    }
+
+    pub fn remove_geoip_database() -> Result<()> {
+        let store = Store::open_default()?;
+        store
+            .delete(KEY_GEOIP_DATABASE)
+            .with_context(|| "Error removing GeoIP database")
+    }
+}
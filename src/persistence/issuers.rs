@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::Persistence;
+
+const KEY_ISSUERS: &str = "issuers";
+const VARIABLE_TEST_ISSUER: &str = "test_issuer";
+const VARIABLE_TEST_SIGNING_KEY: &str = "test_signing_key";
+const TEST_KID: &str = "test";
+
+impl Persistence {
+    pub fn get_issuers(&self) -> Result<Issuers> {
+        let store = Store::open_default()?;
+        Ok(match store.get_json::<Issuers>(self.key(KEY_ISSUERS))? {
+            None => Issuers::new(),
+            Some(i) => i,
+        })
+    }
+
+    // issuers trusted by the validation endpoints, the default scope trusts the test issuer
+    pub fn get_trusted_issuers(&self) -> Result<Issuers> {
+        let issuers = self.get_issuers()?;
+        Ok(match self.tenant {
+            None => issuers.with_test_issuer(),
+            Some(_) => issuers,
+        })
+    }
+
+    pub fn upsert_issuer(&self, policy: IssuerPolicy) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<Issuers>(self.key(KEY_ISSUERS))? {
+            Some(issuers) => issuers,
+            None => Issuers::new(),
+        };
+        match all.position(&policy.issuer) {
+            Ok(idx) => all.issuers[idx] = policy,
+            Err(idx) => all.issuers.insert(idx, policy),
+        }
+        store
+            .set_json(self.key(KEY_ISSUERS), &all)
+            .with_context(|| "Error storing issuer policy")
+    }
+
+    pub fn remove_issuers(&self, values: Vec<String>) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<Issuers>(self.key(KEY_ISSUERS))? {
+            Some(issuers) => issuers,
+            None => Issuers::new(),
+        };
+        all.issuers
+            .retain(|policy| !values.contains(&policy.issuer));
+        store
+            .set_json(self.key(KEY_ISSUERS), &all)
+            .with_context(|| "Error storing issuer policy")
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Issuers {
+    pub issuers: Vec<IssuerPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct IssuerPolicy {
+    pub issuer: String,
+    pub keys: Vec<IssuerKey>,
+    // accepted audiences, any audience is accepted if empty
+    pub audiences: Vec<String>,
+    // maximum allowed lifetime (exp - iat) in seconds
+    pub max_token_lifetime: Option<u64>,
+    // claim keys which must be present in every token of the issuer
+    pub required_claims: Vec<i32>,
+    // tolerated clock skew in seconds, default for all leeways
+    pub clock_skew: u64,
+    // leeway in seconds for individual time claims, overriding clock_skew
+    #[serde(default)]
+    pub exp_leeway: Option<u64>,
+    #[serde(default)]
+    pub nbf_leeway: Option<u64>,
+    #[serde(default)]
+    pub iat_leeway: Option<u64>,
+    // reject tokens without exp
+    #[serde(default)]
+    pub require_expiration: bool,
+    // key tokens are minted with, the first key if not set
+    #[serde(default)]
+    pub active_kid: Option<String>,
+    // hex encoded SHA-256 of the key authorizing token minting, minting is disabled if not set
+    #[serde(default)]
+    pub minting_key_hash: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct IssuerKey {
+    pub kid: String,
+    pub secret: String,
+}
+
+impl IssuerPolicy {
+    // the test issuer signs test tokens with a dedicated key (test_signing_key variable),
+    // it doesn't exist unless the key is set
+    pub fn test_issuer() -> Option<Self> {
+        let secret = spin_sdk::variables::get(VARIABLE_TEST_SIGNING_KEY)
+            .ok()
+            .filter(|secret| !secret.is_empty())?;
+        let issuer = spin_sdk::variables::get(VARIABLE_TEST_ISSUER).ok()?;
+        Some(Self {
+            issuer,
+            keys: vec![IssuerKey {
+                kid: TEST_KID.to_string(),
+                secret,
+            }],
+            audiences: vec![],
+            max_token_lifetime: None,
+            required_claims: vec![],
+            clock_skew: 0,
+            exp_leeway: None,
+            nbf_leeway: None,
+            iat_leeway: None,
+            require_expiration: false,
+            active_kid: None,
+            minting_key_hash: None,
+        })
+    }
+
+    pub fn signing_key(&self) -> Option<&IssuerKey> {
+        match &self.active_kid {
+            Some(kid) => self.keys.iter().find(|key| &key.kid == kid),
+            None => self.keys.first(),
+        }
+    }
+}
+
+impl Issuers {
+    fn new() -> Self {
+        Self { issuers: vec![] }
+    }
+
+    fn position(&self, issuer: &str) -> Result<usize, usize> {
+        self.issuers
+            .binary_search_by(|policy| policy.issuer.as_str().cmp(issuer))
+    }
+
+    pub fn find(&self, issuer: &str) -> Option<&IssuerPolicy> {
+        self.position(issuer).ok().map(|idx| &self.issuers[idx])
+    }
+
+    // a configured policy of the same name takes precedence over the test issuer
+    fn with_test_issuer(mut self) -> Self {
+        if let Some(test_issuer) = IssuerPolicy::test_issuer() {
+            if let Err(idx) = self.position(&test_issuer.issuer) {
+                self.issuers.insert(idx, test_issuer);
+            }
+        }
+        self
+    }
+}
+
+impl IntoBody for Issuers {
+    fn into_body(self) -> Vec<u8> {
+        // key secrets are never handed out
+        let issuers = self
+            .issuers
+            .iter()
+            .map(|policy| {
+                json!({
+                    "issuer": policy.issuer,
+                    "keys": policy.keys.iter().map(|key| json!({"kid": key.kid})).collect::<Vec<_>>(),
+                    "audiences": policy.audiences,
+                    "max_token_lifetime": policy.max_token_lifetime,
+                    "required_claims": policy.required_claims,
+                    "clock_skew": policy.clock_skew,
+                    "exp_leeway": policy.exp_leeway,
+                    "nbf_leeway": policy.nbf_leeway,
+                    "iat_leeway": policy.iat_leeway,
+                    "require_expiration": policy.require_expiration,
+                    "active_kid": policy.active_kid,
+                    "minting": policy.minting_key_hash.is_some(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_vec(&json!({ "issuers": issuers }))
+            .with_context(|| "Error serializing Issuers")
+            .unwrap()
+    }
+}
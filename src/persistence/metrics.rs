@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use spin_sdk::key_value::Store;
+
+use crate::persistence::{atomic, Persistence};
+
+// validations are spread over several shards, so concurrent instances rarely update the same
+// key. Every shard is updated using compare-and-swap, the shards are summed up when read
+const KEY_METRICS: &str = "metrics/";
+const METRIC_SHARDS: u8 = 16;
+
+// metrics are aggregated across all instances (and tenants) of the deployment
+impl Persistence {
+    pub fn get_metrics() -> Result<Metrics> {
+        let store = Store::open_default()?;
+        let mut all = Metrics::default();
+        for shard in 0..METRIC_SHARDS {
+            if let Some(metrics) = store.get_json::<Metrics>(format!("{KEY_METRICS}{shard}"))? {
+                all.merge(metrics);
+            }
+        }
+        Ok(all)
+    }
+
+    pub fn update_metrics(update: impl Fn(&mut Metrics)) -> Result<()> {
+        let mut sample = [0u8; 1];
+        getrandom::getrandom(&mut sample).with_context(|| "Error selecting metrics shard")?;
+        let key = format!("{KEY_METRICS}{}", sample[0] % METRIC_SHARDS);
+        atomic::update_json::<Metrics, ()>(&key, |metrics| {
+            let mut metrics = metrics.unwrap_or_default();
+            update(&mut metrics);
+            Ok((Some(metrics), ()))
+        })
+    }
+}
+
+/// Counters and histograms keyed by metric name and rendered label set
+#[derive(Deserialize, Serialize, Default)]
+pub struct Metrics {
+    pub counters: BTreeMap<String, BTreeMap<String, u64>>,
+    pub histograms: BTreeMap<String, BTreeMap<String, Histogram>>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct Histogram {
+    // cumulative counts per bucket bound, in the order of the bounds
+    pub buckets: Vec<u64>,
+    pub sum_micros: u64,
+    pub count: u64,
+}
+
+impl Metrics {
+    pub fn increment(&mut self, name: &str, labels: String) {
+        *self
+            .counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(labels)
+            .or_default() += 1;
+    }
+
+    // adds the counts of another shard
+    pub fn merge(&mut self, other: Metrics) {
+        for (name, series) in other.counters {
+            let counters = self.counters.entry(name).or_default();
+            for (labels, value) in series {
+                *counters.entry(labels).or_default() += value;
+            }
+        }
+        for (name, series) in other.histograms {
+            let histograms = self.histograms.entry(name).or_default();
+            for (labels, other) in series {
+                let histogram = histograms.entry(labels).or_default();
+                histogram
+                    .buckets
+                    .resize(histogram.buckets.len().max(other.buckets.len()), 0);
+                for (bucket, count) in histogram.buckets.iter_mut().zip(other.buckets) {
+                    *bucket += count;
+                }
+                histogram.sum_micros += other.sum_micros;
+                histogram.count += other.count;
+            }
+        }
+    }
+
+    pub fn observe(&mut self, name: &str, labels: String, bounds: &[u64], micros: u64) {
+        let histogram = self
+            .histograms
+            .entry(name.to_string())
+            .or_default()
+            .entry(labels)
+            .or_default();
+        histogram.buckets.resize(bounds.len(), 0);
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(bounds) {
+            if micros <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum_micros += micros;
+        histogram.count += 1;
+    }
+}
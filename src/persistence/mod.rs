@@ -7,22 +7,104 @@ use spin_sdk::{http::conversions::IntoBody, key_value::Store};
 
 use crate::asn_resolver;
 
+mod atomic;
+mod audit;
+mod geoip;
+mod issuers;
+mod metrics;
+mod profiles;
+mod proofs;
+mod rate_limits;
+mod revocations;
+mod rules;
+mod sessions;
+mod settings;
+mod templates;
+mod tenants;
+
+pub use issuers::*;
+pub use metrics::*;
+pub use profiles::*;
+pub use rate_limits::*;
+pub use revocations::*;
+pub use rules::*;
+pub use sessions::*;
+pub use settings::*;
+pub use templates::*;
+pub use tenants::*;
+
 const KEY_BLOCKED: &str = "blocked";
+// one in SWEEP_SAMPLE writes of expiring entries also removes the expired ones, so entries of
+// clients which never return don't pile up in the store
+const SWEEP_SAMPLE: u8 = 64;
 
-pub struct Persistence {}
+/// Access to the data of the default scope or of a single tenant
+pub struct Persistence {
+    // tenant data is stored below tenants/<id>/, the default scope uses the plain keys
+    tenant: Option<String>,
+}
 
 impl Persistence {
-    pub fn get_blocking_data() -> Result<BlockedData> {
+    pub fn default_scope() -> Self {
+        Self { tenant: None }
+    }
+
+    pub fn for_tenant(tenant: &str) -> Self {
+        Self {
+            tenant: Some(tenant.to_string()),
+        }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    fn key(&self, key: &str) -> String {
+        match &self.tenant {
+            None => key.to_string(),
+            Some(tenant) => format!("tenants/{tenant}/{key}"),
+        }
+    }
+
+    // removes the keys below prefix which are expired, sampled as it lists the entire store
+    fn sweep_expired(
+        &self,
+        store: &Store,
+        prefix: &str,
+        expired: impl Fn(&str) -> Result<bool>,
+    ) -> Result<()> {
+        let mut sample = [0u8; 1];
+        if getrandom::getrandom(&mut sample).is_err() || sample[0] % SWEEP_SAMPLE != 0 {
+            return Ok(());
+        }
+        let prefix = self.key(prefix);
+        for key in store.get_keys()? {
+            if key.starts_with(&prefix) && expired(&key)? {
+                store
+                    .delete(&key)
+                    .with_context(|| "Error removing expired entry")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_blocking_data(&self) -> Result<BlockedData> {
         let store = Store::open_default()?;
-        Ok(match store.get_json::<BlockedData>(KEY_BLOCKED)? {
-            None => BlockedData::new(),
-            Some(b) => b,
-        })
+        Ok(
+            match store.get_json::<BlockedData>(self.key(KEY_BLOCKED))? {
+                None => BlockedData::new(),
+                Some(b) => b,
+            },
+        )
     }
 
-    pub fn add_items_to_blocklist(kind: BlockedClaimType, values: Vec<String>) -> Result<()> {
+    pub fn add_items_to_blocklist(
+        &self,
+        kind: BlockedClaimType,
+        values: Vec<String>,
+    ) -> Result<()> {
         let store = Store::open_default()?;
-        let mut all = match store.get_json::<BlockedData>(KEY_BLOCKED)? {
+        let mut all = match store.get_json::<BlockedData>(self.key(KEY_BLOCKED))? {
             Some(blocking) => blocking,
             None => BlockedData::new(),
         };
@@ -34,33 +116,36 @@ impl Persistence {
         }
         all.optimize();
         store
-            .set_json(KEY_BLOCKED, &all)
+            .set_json(self.key(KEY_BLOCKED), &all)
             .with_context(|| "Error storing value in block list")
     }
 
-    pub fn remove_items_from_blocklist(kind: BlockedClaimType, values: Vec<String>) -> Result<()> {
+    pub fn remove_items_from_blocklist(
+        &self,
+        kind: BlockedClaimType,
+        values: Vec<String>,
+    ) -> Result<()> {
         let store = Store::open_default()?;
-        let mut all = match store.get_json::<BlockedData>(KEY_BLOCKED)? {
+        let mut all = match store.get_json::<BlockedData>(self.key(KEY_BLOCKED))? {
             Some(blocking) => blocking,
             None => BlockedData::new(),
         };
         for value in values {
-            match all.contains(&kind, &value) {
-                Ok(idx) => all.remove_at(&kind, idx),
-                Err(_) => (),
+            if let Ok(idx) = all.contains(&kind, &value) {
+                all.remove_at(&kind, idx)
             }
         }
         all.optimize();
 
         store
-            .set_json(KEY_BLOCKED, &all)
+            .set_json(self.key(KEY_BLOCKED), &all)
             .with_context(|| "Error storing value in block list")
     }
 }
 impl Persistence {
-    pub async fn add_asns_to_blocklist(values: Vec<u32>) -> Result<()> {
+    pub async fn add_asns_to_blocklist(&self, values: Vec<u32>) -> Result<()> {
         let store = Store::open_default()?;
-        let mut all = match store.get_json::<BlockedData>(KEY_BLOCKED)? {
+        let mut all = match store.get_json::<BlockedData>(self.key(KEY_BLOCKED))? {
             Some(blocking) => blocking,
             None => BlockedData::new(),
         };
@@ -82,13 +167,13 @@ impl Persistence {
         all.optimize();
 
         store
-            .set_json(KEY_BLOCKED, &all)
+            .set_json(self.key(KEY_BLOCKED), &all)
             .with_context(|| "Error while updating block data in KV")
     }
 
-    pub fn remove_asns_from_blocklist(values: Vec<u32>) -> Result<()> {
+    pub fn remove_asns_from_blocklist(&self, values: Vec<u32>) -> Result<()> {
         let store = Store::open_default()?;
-        let mut all = match store.get_json::<BlockedData>(KEY_BLOCKED)? {
+        let mut all = match store.get_json::<BlockedData>(self.key(KEY_BLOCKED))? {
             Some(blocking) => blocking,
             None => BlockedData::new(),
         };
@@ -100,11 +185,13 @@ impl Persistence {
         all.optimize();
 
         store
-            .set_json(KEY_BLOCKED, &all)
+            .set_json(self.key(KEY_BLOCKED), &all)
             .with_context(|| "Error storing value in block list")
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum BlockedClaimType {
     Subject,
     Country,
@@ -160,7 +247,7 @@ impl Ord for Asn {
 }
 
 impl BlockedData {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             any: false,
             any_asns: false,
@@ -176,7 +263,7 @@ impl BlockedData {
         }
     }
 
-    fn optimize(&mut self) {
+    pub(crate) fn optimize(&mut self) {
         self.asns.sort();
         self.any_asns = !self.asns.is_empty();
         self.countries.sort();
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::{BlockedClaimType, ClaimPolicy, Persistence, RateLimit, SessionPolicy};
+
+const KEY_PROFILES: &str = "profiles";
+
+impl Persistence {
+    pub fn get_profiles(&self) -> Result<Profiles> {
+        let store = Store::open_default()?;
+        Ok(match store.get_json::<Profiles>(self.key(KEY_PROFILES))? {
+            None => Profiles::new(),
+            Some(p) => p,
+        })
+    }
+
+    pub fn upsert_profile(&self, profile: ValidationProfile) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<Profiles>(self.key(KEY_PROFILES))? {
+            Some(profiles) => profiles,
+            None => Profiles::new(),
+        };
+        match all.position(&profile.name) {
+            Ok(idx) => all.profiles[idx] = profile,
+            Err(idx) => all.profiles.insert(idx, profile),
+        }
+        store
+            .set_json(self.key(KEY_PROFILES), &all)
+            .with_context(|| "Error storing validation profile")
+    }
+
+    pub fn remove_profiles(&self, values: Vec<String>) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<Profiles>(self.key(KEY_PROFILES))? {
+            Some(profiles) => profiles,
+            None => Profiles::new(),
+        };
+        all.profiles
+            .retain(|profile| !values.contains(&profile.name));
+        store
+            .set_json(self.key(KEY_PROFILES), &all)
+            .with_context(|| "Error storing validation profile")
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Profiles {
+    pub profiles: Vec<ValidationProfile>,
+}
+
+/// A named configuration of the validation pipeline
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ValidationProfile {
+    pub name: String,
+    // per claim configuration, overrides the claims setting
+    #[serde(default)]
+    pub claims: BTreeMap<i32, ClaimPolicy>,
+    // check the revocation list
+    #[serde(default)]
+    pub revocation: bool,
+    // blocklist categories applied to the request
+    #[serde(default)]
+    pub blocklists: Vec<BlockedClaimType>,
+    // reject proofs of possession presented before (jti)
+    #[serde(default)]
+    pub replay_protection: bool,
+    // accepted audiences, any audience is accepted if empty
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    // leeway in seconds, overriding the issuer policy
+    #[serde(default)]
+    pub exp_leeway: Option<u64>,
+    #[serde(default)]
+    pub nbf_leeway: Option<u64>,
+    #[serde(default)]
+    pub iat_leeway: Option<u64>,
+    // maximum lifetime (exp - iat) in seconds, the stricter of this and the issuer policy applies
+    #[serde(default)]
+    pub max_token_lifetime: Option<u64>,
+    // reject tokens without exp, also applies if the issuer policy requires it
+    #[serde(default)]
+    pub require_expiration: bool,
+    // limits of validations per subject, cti or client IP
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimit>,
+    // limit of concurrent sessions per subject
+    #[serde(default)]
+    pub sessions: Option<SessionPolicy>,
+}
+
+impl ValidationProfile {
+    // profile of /validate
+    pub fn full() -> Self {
+        Self {
+            name: String::from("default"),
+            claims: BTreeMap::new(),
+            revocation: true,
+            blocklists: vec![
+                BlockedClaimType::Subject,
+                BlockedClaimType::Country,
+                BlockedClaimType::Cidr,
+                BlockedClaimType::UserAgent,
+            ],
+            replay_protection: true,
+            audiences: vec![],
+            exp_leeway: None,
+            nbf_leeway: None,
+            iat_leeway: None,
+            max_token_lifetime: None,
+            require_expiration: false,
+            rate_limits: vec![],
+            sessions: None,
+        }
+    }
+
+    // profile of /validate/simple, without checks backed by the key-value store
+    pub fn simple() -> Self {
+        Self {
+            name: String::from("simple"),
+            revocation: false,
+            blocklists: vec![],
+            replay_protection: false,
+            ..Self::full()
+        }
+    }
+
+    // claims configured by the profile take precedence over the claims setting
+    pub fn with_default_claims(mut self, claims: &BTreeMap<i32, ClaimPolicy>) -> Self {
+        let mut merged = claims.clone();
+        merged.append(&mut self.claims);
+        self.claims = merged;
+        self
+    }
+}
+
+impl Profiles {
+    fn new() -> Self {
+        Self { profiles: vec![] }
+    }
+
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        self.profiles
+            .binary_search_by(|profile| profile.name.as_str().cmp(name))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ValidationProfile> {
+        self.position(name).ok().map(|idx| &self.profiles[idx])
+    }
+}
+
+impl IntoBody for Profiles {
+    fn into_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self)
+            .with_context(|| "Error serializing Profiles")
+            .unwrap()
+    }
+}
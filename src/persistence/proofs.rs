@@ -0,0 +1,47 @@
+use anyhow::Result;
+use common_access_token::current_timestamp;
+use sha2::{Digest, Sha256};
+use spin_sdk::key_value::Store;
+
+use crate::persistence::{atomic, Persistence};
+
+const KEY_PROOFS: &str = "proofs/";
+const KEY_TOKEN_USES: &str = "token-uses/";
+
+impl Persistence {
+    // records the jti of a proof of possession, returns false if it has been presented before.
+    // Every jti has its own key holding the expiry of the proof, so concurrent replays of the
+    // same proof are detected by the compare-and-swap
+    pub fn register_proof(&self, store: &Store, jti: &str, exp: u64) -> Result<bool> {
+        self.register_use(store, KEY_PROOFS, jti.as_bytes(), exp)
+    }
+
+    // records the cti of a token restricted by catreplay, returns false if it has been
+    // presented before. The entry is kept until the token expires
+    pub fn register_token_use(&self, store: &Store, cti: &[u8], exp: u64) -> Result<bool> {
+        self.register_use(store, KEY_TOKEN_USES, cti, exp)
+    }
+
+    fn register_use(&self, store: &Store, prefix: &str, id: &[u8], exp: u64) -> Result<bool> {
+        let now = current_timestamp();
+        let key = self.key(&format!("{prefix}{}", use_id(id)));
+        let fresh = atomic::update_json::<u64, bool>(&key, |used| match used {
+            // expired proofs and tokens are rejected anyway
+            Some(used) if used > now => Ok((None, false)),
+            _ => Ok((Some(exp), true)),
+        })?;
+        self.sweep_expired(store, prefix, |key| {
+            Ok(store.get_json::<u64>(key)?.is_some_and(|used| used <= now))
+        })?;
+        Ok(fresh)
+    }
+}
+
+// jtis and ctis are chosen by clients and issuers, the hash keeps keys short and free of
+// separators
+fn use_id(id: &[u8]) -> String {
+    Sha256::digest(id)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use common_access_token::current_timestamp;
+use serde::{Deserialize, Serialize};
+use spin_sdk::key_value::Store;
+
+use crate::persistence::{atomic, Persistence};
+
+const KEY_RATE_LIMITS: &str = "rate-limits/";
+
+impl Persistence {
+    // counts a request in the sliding window of the given counter. Every window has its own
+    // counter key (rate-limits/<counter>/<window>/<start>) which is incremented atomically, so
+    // concurrent requests are never lost. Requests rejected after all have to be released
+    pub fn hit_rate_limit(
+        &self,
+        store: &Store,
+        counter: &str,
+        limit: u64,
+        window: u64,
+    ) -> Result<RateLimitHit> {
+        let now = current_timestamp();
+        let start = window_start(now, window);
+        let key = |start: u64| self.key(&format!("{KEY_RATE_LIMITS}{counter}/{window}/{start}"));
+        let current = atomic::increment(&key(start), 1)?.max(0) as u64;
+        // windows before the previous one don't count, their keys are removed from time to time
+        let previous = match start.checked_sub(window) {
+            Some(previous) => atomic::increment(&key(previous), 0)?.max(0) as u64,
+            None => 0,
+        };
+        self.sweep_expired(store, KEY_RATE_LIMITS, |key| Ok(is_expired(key, now)))?;
+        let elapsed = now - start;
+        Ok(RateLimitHit {
+            key: key(start),
+            retry_after: match estimate(previous, current, window, elapsed) > limit {
+                true => Some((window - elapsed).max(1)),
+                false => None,
+            },
+        })
+    }
+
+    // takes a request back out of the window it was counted in
+    pub fn release_rate_limit(&self, hit: &RateLimitHit) -> Result<()> {
+        atomic::increment(&hit.key, -1).map(|_| ())
+    }
+}
+
+/// A request counted in the window of a rate limit
+pub struct RateLimitHit {
+    key: String,
+    // seconds to wait before retrying if the limit has been exceeded
+    pub retry_after: Option<u64>,
+}
+
+// start of the fixed window now belongs to
+fn window_start(now: u64, window: u64) -> u64 {
+    now - now % window
+}
+
+// requests within the sliding window, the previous window is weighted by its overlap with the
+// sliding window
+fn estimate(previous: u64, current: u64, window: u64, elapsed: u64) -> u64 {
+    previous * (window - elapsed) / window + current
+}
+
+// a window no longer counts once the one following it has ended
+fn is_expired(key: &str, now: u64) -> bool {
+    let mut segments = key.rsplit('/');
+    let (Some(Ok(start)), Some(Ok(window))) = (
+        segments.next().map(str::parse::<u64>),
+        segments.next().map(str::parse::<u64>),
+    ) else {
+        return false;
+    };
+    start.saturating_add(window.saturating_mul(2)) <= now
+}
+
+/// Limit of validations per window for one attribute of the request
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RateLimit {
+    pub key: RateLimitKey,
+    // validations allowed per window
+    pub limit: u64,
+    // length of the window in seconds
+    pub window: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    Subject,
+    Cti,
+    ClientIp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_starts_at_multiples_of_the_window() {
+        assert_eq!(window_start(1_000, 60), 960);
+        assert_eq!(window_start(960, 60), 960);
+        assert_eq!(window_start(1_019, 60), 960);
+        assert_eq!(window_start(1_020, 60), 1_020);
+    }
+
+    #[test]
+    fn previous_window_is_weighted_by_its_overlap() {
+        // at the start of the window the previous one counts entirely
+        assert_eq!(estimate(10, 0, 60, 0), 10);
+        assert_eq!(estimate(10, 2, 60, 30), 7);
+        // shortly before the end of the window hardly anything is left of the previous one
+        assert_eq!(estimate(10, 4, 60, 59), 4);
+        assert_eq!(estimate(0, 5, 60, 10), 5);
+    }
+
+    #[test]
+    fn windows_expire_once_the_following_window_has_ended() {
+        let key = "tenants/acme/rate-limits/default/sub/user/42/60/960";
+        assert!(!is_expired(key, 960));
+        assert!(!is_expired(key, 1_079));
+        assert!(is_expired(key, 1_080));
+    }
+
+    #[test]
+    fn keys_without_window_are_never_expired() {
+        assert!(!is_expired("rate-limits/default/sub/user", u64::MAX));
+        assert!(!is_expired("rate-limits/60", u64::MAX));
+    }
+}
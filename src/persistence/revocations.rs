@@ -0,0 +1,134 @@
+use std::cmp::Ordering;
+
+use anyhow::{Context, Result};
+use common_access_token::current_timestamp;
+use serde::{Deserialize, Serialize};
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::Persistence;
+
+const KEY_REVOKED: &str = "revoked";
+
+impl Persistence {
+    pub fn get_revoked_tokens(&self) -> Result<RevokedTokens> {
+        let store = Store::open_default()?;
+        Ok(
+            match store.get_json::<RevokedTokens>(self.key(KEY_REVOKED))? {
+                None => RevokedTokens::new(),
+                Some(r) => r,
+            },
+        )
+    }
+
+    pub fn revoke_tokens(&self, values: Vec<RevokedToken>) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<RevokedTokens>(self.key(KEY_REVOKED))? {
+            Some(revoked) => revoked,
+            None => RevokedTokens::new(),
+        };
+        for value in values {
+            match all.tokens.binary_search(&value) {
+                // revoking twice updates the expiration of the existing entry
+                Ok(idx) => all.tokens[idx].exp = value.exp,
+                Err(idx) => all.tokens.insert(idx, value),
+            }
+        }
+        all.optimize();
+        store
+            .set_json(self.key(KEY_REVOKED), &all)
+            .with_context(|| "Error storing value in revocation list")
+    }
+
+    pub fn unrevoke_tokens(&self, values: Vec<String>) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<RevokedTokens>(self.key(KEY_REVOKED))? {
+            Some(revoked) => revoked,
+            None => RevokedTokens::new(),
+        };
+        // entries stored before ctis were normalized are matched by their canonical form as well
+        all.tokens.retain(|revoked| {
+            !values.contains(&revoked.cti)
+                && !canonical_cti(&revoked.cti).is_some_and(|cti| values.contains(&cti))
+        });
+        all.optimize();
+        store
+            .set_json(self.key(KEY_REVOKED), &all)
+            .with_context(|| "Error storing value in revocation list")
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RevokedTokens {
+    pub any: bool,
+    pub tokens: Vec<RevokedToken>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct RevokedToken {
+    // base64url encoded CWT ID (cti) of the revoked token
+    pub cti: String,
+    // expiration (exp) of the revoked token, entries are dropped once it has passed
+    pub exp: Option<u64>,
+}
+
+impl PartialOrd for RevokedToken {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other)) // delegate to Ord
+    }
+}
+
+impl Ord for RevokedToken {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cti.cmp(&other.cti) // compare by cti only
+    }
+}
+
+impl RevokedToken {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.exp.is_some_and(|exp| exp <= now)
+    }
+}
+
+// ctis are stored base64url encoded without padding, the encoding the revocation check uses
+pub fn canonical_cti(cti: &str) -> Option<String> {
+    base64_url::decode(cti.trim().trim_end_matches('='))
+        .ok()
+        .map(|cti| base64_url::encode(&cti))
+}
+
+impl RevokedTokens {
+    fn new() -> Self {
+        Self {
+            any: false,
+            tokens: vec![],
+        }
+    }
+
+    fn optimize(&mut self) {
+        let now = current_timestamp();
+        self.tokens.retain(|revoked| !revoked.is_expired(now));
+        self.tokens.sort();
+        self.any = !self.tokens.is_empty();
+    }
+}
+
+impl IntoBody for RevokedTokens {
+    fn into_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self)
+            .with_context(|| "Error serializing RevokedTokens")
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ctis_are_normalized() {
+        assert_eq!(canonical_cti("dG9rZW4tMQ").as_deref(), Some("dG9rZW4tMQ"));
+        assert_eq!(canonical_cti("dG9rZW4tMQ==").as_deref(), Some("dG9rZW4tMQ"));
+        assert_eq!(canonical_cti(" dG9rZW4tMQ ").as_deref(), Some("dG9rZW4tMQ"));
+        assert!(canonical_cti("not base64url!").is_none());
+    }
+}
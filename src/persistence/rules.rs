@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Declarative rule for a custom (private) claim
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClaimRule {
+    // expected CBOR type of the claim
+    #[serde(rename = "type")]
+    pub claim_type: ClaimType,
+    // the claim only has to be of the expected type if no condition is set
+    #[serde(default)]
+    pub condition: Option<ClaimCondition>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClaimType {
+    Integer,
+    Text,
+    Bytes,
+    Array,
+    Map,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimCondition {
+    // Integer and Text claims are compared with numbers and strings, Bytes with base64url strings
+    Equals(Value),
+    In(Vec<Value>),
+    // inclusive bounds of Integer claims
+    Range {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Regex(String),
+    // <claim> <operator> <request field>
+    Compare {
+        operator: CompareOperator,
+        field: RequestField,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareOperator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestField {
+    Method,
+    Url,
+    ClientIp,
+    Country,
+    Alpn,
+    Header(String),
+}
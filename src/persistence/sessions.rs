@@ -0,0 +1,129 @@
+use anyhow::{Context, Result};
+use common_access_token::current_timestamp;
+use serde::{Deserialize, Serialize};
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::{atomic, Persistence};
+
+const KEY_SESSIONS: &str = "sessions";
+
+impl Persistence {
+    pub fn get_sessions(&self, subject: &str) -> Result<Sessions> {
+        let store = Store::open_default()?;
+        let mut all = store
+            .get_json::<Sessions>(self.key(&format!("{KEY_SESSIONS}/{subject}")))?
+            .unwrap_or_else(Sessions::new);
+        all.prune(current_timestamp());
+        Ok(all)
+    }
+
+    // records a heartbeat of the session, new sessions are only started below the limit. The
+    // sessions of a subject are updated using compare-and-swap, so concurrent validations can't
+    // exceed the limit
+    pub fn register_session(
+        &self,
+        subject: &str,
+        id: &str,
+        max_sessions: u32,
+        ttl: u64,
+    ) -> Result<SessionOutcome> {
+        let key = self.key(&format!("{KEY_SESSIONS}/{subject}"));
+        let now = current_timestamp();
+        atomic::update_json::<Sessions, SessionOutcome>(&key, |all| {
+            let mut all = all.unwrap_or_else(Sessions::new);
+            all.prune(now);
+            let active = all.active();
+            match all.sessions.iter_mut().find(|session| session.id == id) {
+                Some(session) if session.kicked => return Ok((None, SessionOutcome::Terminated)),
+                Some(session) => session.expires = now + ttl,
+                None if active >= max_sessions as usize => {
+                    return Ok((None, SessionOutcome::LimitReached))
+                }
+                None => {
+                    all.sessions.push(Session {
+                        id: id.to_string(),
+                        started: now,
+                        expires: now + ttl,
+                        kicked: false,
+                    });
+                }
+            }
+            Ok((Some(all), SessionOutcome::Active))
+        })
+    }
+
+    // kicked sessions can't be resumed until their last heartbeat has expired
+    pub fn kick_sessions(&self, subject: &str, ids: Vec<String>) -> Result<()> {
+        let key = self.key(&format!("{KEY_SESSIONS}/{subject}"));
+        let now = current_timestamp();
+        atomic::update_json::<Sessions, ()>(&key, |all| {
+            // nothing to kick for unknown subjects
+            let Some(mut all) = all else {
+                return Ok((None, ()));
+            };
+            all.prune(now);
+            for session in all.sessions.iter_mut() {
+                if ids.contains(&session.id) {
+                    session.kicked = true;
+                }
+            }
+            Ok((Some(all), ()))
+        })
+    }
+}
+
+/// Limit of concurrent sessions (devices) per subject
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SessionPolicy {
+    pub max_sessions: u32,
+    // sessions without heartbeat (validation) for this many seconds end
+    pub ttl: u64,
+    // header carrying the session id, the cti of the token is used if not set
+    #[serde(default)]
+    pub session_header: Option<String>,
+}
+
+pub enum SessionOutcome {
+    Active,
+    LimitReached,
+    Terminated,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Sessions {
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub started: u64,
+    // end of the session unless another heartbeat arrives
+    pub expires: u64,
+    pub kicked: bool,
+}
+
+impl Sessions {
+    fn new() -> Self {
+        Self { sessions: vec![] }
+    }
+
+    fn prune(&mut self, now: u64) {
+        self.sessions.retain(|session| session.expires > now);
+    }
+
+    fn active(&self) -> usize {
+        self.sessions
+            .iter()
+            .filter(|session| !session.kicked)
+            .count()
+    }
+}
+
+impl IntoBody for Sessions {
+    fn into_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self)
+            .with_context(|| "Error serializing Sessions")
+            .unwrap()
+    }
+}
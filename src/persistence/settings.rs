@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::{ClaimRule, Persistence};
+
+const KEY_SETTINGS: &str = "settings";
+
+impl Persistence {
+    pub fn get_settings(&self) -> Result<Settings> {
+        let store = Store::open_default()?;
+        Ok(store
+            .get_json::<Settings>(self.key(KEY_SETTINGS))?
+            .unwrap_or_default())
+    }
+
+    pub fn set_settings(&self, settings: Settings) -> Result<()> {
+        let store = Store::open_default()?;
+        store
+            .set_json(self.key(KEY_SETTINGS), &settings)
+            .with_context(|| "Error storing settings")
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    // how geo claims are treated if the client location can't be determined
+    pub unknown_location: UnknownLocationPolicy,
+    // how cath rules apply to headers presented multiple times
+    pub header_match: HeaderMatchMode,
+    // HEAD requests are granted by tokens allowing GET (catm)
+    pub head_implied_by_get: bool,
+    // CORS preflight requests (OPTIONS) are granted by tokens allowing the announced method (catm)
+    pub allow_preflight: bool,
+    // per claim configuration of the validation pipeline, keyed by claim key
+    pub claims: BTreeMap<i32, ClaimPolicy>,
+    // declarative rules for custom claims, keyed by claim key
+    pub custom_claims: BTreeMap<i32, ClaimRule>,
+    // structured logging of validation decisions
+    pub decision_log: DecisionLogSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            unknown_location: UnknownLocationPolicy::default(),
+            header_match: HeaderMatchMode::default(),
+            head_implied_by_get: true,
+            allow_preflight: true,
+            claims: BTreeMap::new(),
+            custom_claims: BTreeMap::new(),
+            decision_log: DecisionLogSettings::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+#[serde(default)]
+pub struct DecisionLogSettings {
+    // share of successful validations which are logged, between 0 and 1
+    pub success_sample_rate: f64,
+    // share of failed validations which are logged, between 0 and 1
+    pub failure_sample_rate: f64,
+    // log the HMAC-SHA256 (hex, keyed by the decision_log_secret variable) of sub instead of
+    // the subject
+    pub hash_subject: bool,
+}
+
+impl Default for DecisionLogSettings {
+    fn default() -> Self {
+        Self {
+            success_sample_rate: 1.0,
+            failure_sample_rate: 1.0,
+            hash_subject: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct ClaimPolicy {
+    // disabled claims are ignored
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    // tokens without the claim are rejected
+    #[serde(default)]
+    pub required: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownLocationPolicy {
+    // geo claims are skipped
+    Allow,
+    // tokens carrying geo claims are rejected
+    #[default]
+    Deny,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderMatchMode {
+    // at least one of the values has to match
+    #[default]
+    Any,
+    // every value has to match
+    All,
+}
+
+impl IntoBody for Settings {
+    fn into_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self)
+            .with_context(|| "Error serializing Settings")
+            .unwrap()
+    }
+}
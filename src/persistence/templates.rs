@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::Persistence;
+
+const KEY_TEMPLATES: &str = "templates";
+
+impl Persistence {
+    pub fn get_templates(&self) -> Result<Templates> {
+        let store = Store::open_default()?;
+        Ok(
+            match store.get_json::<Templates>(self.key(KEY_TEMPLATES))? {
+                None => Templates::new(),
+                Some(t) => t,
+            },
+        )
+    }
+
+    pub fn upsert_template(&self, template: ClaimTemplate) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<Templates>(self.key(KEY_TEMPLATES))? {
+            Some(templates) => templates,
+            None => Templates::new(),
+        };
+        match all.position(&template.name) {
+            Ok(idx) => all.templates[idx] = template,
+            Err(idx) => all.templates.insert(idx, template),
+        }
+        store
+            .set_json(self.key(KEY_TEMPLATES), &all)
+            .with_context(|| "Error storing claim template")
+    }
+
+    pub fn remove_templates(&self, values: Vec<String>) -> Result<()> {
+        let store = Store::open_default()?;
+        let mut all = match store.get_json::<Templates>(self.key(KEY_TEMPLATES))? {
+            Some(templates) => templates,
+            None => Templates::new(),
+        };
+        all.templates
+            .retain(|template| !values.contains(&template.name));
+        store
+            .set_json(self.key(KEY_TEMPLATES), &all)
+            .with_context(|| "Error storing claim template")
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Templates {
+    pub templates: Vec<ClaimTemplate>,
+}
+
+/// Claims of minted tokens, requests may override sub, countries, url_prefix and lifetime
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClaimTemplate {
+    pub name: String,
+    pub audience: Option<String>,
+    // seconds from iat to exp
+    pub lifetime: u64,
+    // scheme, host, port and path prefix of the URLs the token is valid for (catu)
+    pub url_prefix: Option<String>,
+    // allowed HTTP methods (catm)
+    pub methods: Vec<String>,
+    // ISO 3166 country or region codes (catgeoiso3166)
+    pub countries: Vec<String>,
+    // ALPN protocols (catalpn)
+    pub alpn: Vec<String>,
+}
+
+impl Templates {
+    fn new() -> Self {
+        Self { templates: vec![] }
+    }
+
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        self.templates
+            .binary_search_by(|template| template.name.as_str().cmp(name))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ClaimTemplate> {
+        self.position(name).ok().map(|idx| &self.templates[idx])
+    }
+}
+
+impl IntoBody for Templates {
+    fn into_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self)
+            .with_context(|| "Error serializing Templates")
+            .unwrap()
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use anyhow::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use spin_sdk::{http::conversions::IntoBody, key_value::Store};
+
+use crate::persistence::Persistence;
+
+// every tenant has its own key, hosts are indexed so resolving a request reads a single tenant
+const KEY_TENANTS: &str = "tenant-registry/";
+const KEY_TENANT_HOSTS: &str = "tenant-hosts/";
+
+// the tenant registry is shared by the entire deployment
+impl Persistence {
+    pub fn get_tenants() -> Result<Tenants> {
+        let store = Store::open_default()?;
+        let mut tenants = vec![];
+        for key in store.get_keys()? {
+            if !key.starts_with(KEY_TENANTS) {
+                continue;
+            }
+            if let Some(tenant) = store.get_json::<Tenant>(&key)? {
+                tenants.push(tenant);
+            }
+        }
+        tenants.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Tenants { tenants })
+    }
+
+    pub fn find_tenant(id: &str) -> Result<Option<Tenant>> {
+        let store = Store::open_default()?;
+        store.get_json::<Tenant>(tenant_key(id))
+    }
+
+    pub fn find_tenant_by_host(host: &str) -> Result<Option<Tenant>> {
+        let store = Store::open_default()?;
+        match store.get_json::<String>(host_key(host))? {
+            Some(id) => store.get_json::<Tenant>(tenant_key(&id)),
+            None => Ok(None),
+        }
+    }
+
+    // fails if one of the hosts belongs to another tenant
+    pub fn upsert_tenant(tenant: Tenant) -> Result<()> {
+        let store = Store::open_default()?;
+        for host in &tenant.hosts {
+            if store
+                .get_json::<String>(host_key(host))?
+                .is_some_and(|id| id != tenant.id)
+            {
+                return Err(Error::new(HostConflictError(host.clone())));
+            }
+        }
+        if let Some(previous) = store.get_json::<Tenant>(tenant_key(&tenant.id))? {
+            remove_hosts(&store, &previous)?;
+        }
+        for host in &tenant.hosts {
+            store
+                .set_json(host_key(host), &tenant.id)
+                .with_context(|| "Error storing tenant")?;
+        }
+        store
+            .set_json(tenant_key(&tenant.id), &tenant)
+            .with_context(|| "Error storing tenant")
+    }
+
+    // removes the tenants along with all of their data
+    pub fn remove_tenants(values: Vec<String>) -> Result<()> {
+        let store = Store::open_default()?;
+        for id in &values {
+            if let Some(tenant) = store.get_json::<Tenant>(tenant_key(id))? {
+                remove_hosts(&store, &tenant)?;
+            }
+            store
+                .delete(&tenant_key(id))
+                .with_context(|| "Error removing tenant")?;
+        }
+        for key in store.get_keys()? {
+            if values
+                .iter()
+                .any(|id| key.starts_with(&Self::for_tenant(id).key("")))
+            {
+                store
+                    .delete(&key)
+                    .with_context(|| "Error removing tenant data")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tenant_key(id: &str) -> String {
+    format!("{KEY_TENANTS}{id}")
+}
+
+fn host_key(host: &str) -> String {
+    format!("{KEY_TENANT_HOSTS}{}", host.to_ascii_lowercase())
+}
+
+fn remove_hosts(store: &Store, tenant: &Tenant) -> Result<()> {
+    for host in &tenant.hosts {
+        store
+            .delete(&host_key(host))
+            .with_context(|| "Error removing tenant host")?;
+    }
+    Ok(())
+}
+
+/// A host is already registered for another tenant
+#[derive(Debug)]
+pub struct HostConflictError(pub String);
+
+impl fmt::Display for HostConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "host {} belongs to another tenant", self.0)
+    }
+}
+
+impl std::error::Error for HostConflictError {}
+
+pub struct Tenants {
+    pub tenants: Vec<Tenant>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Tenant {
+    pub id: String,
+    // requests for these hosts belong to the tenant
+    pub hosts: Vec<String>,
+    // hex encoded SHA-256 of the key authorizing the admin API of the tenant
+    pub admin_key_hash: String,
+}
+
+impl IntoBody for Tenants {
+    fn into_body(self) -> Vec<u8> {
+        // admin keys are never handed out
+        let tenants = self
+            .tenants
+            .iter()
+            .map(|tenant| json!({"id": tenant.id, "hosts": tenant.hosts}))
+            .collect::<Vec<_>>();
+        serde_json::to_vec(&json!({ "tenants": tenants }))
+            .with_context(|| "Error serializing Tenants")
+            .unwrap()
+    }
+}
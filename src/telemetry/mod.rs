@@ -0,0 +1,336 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error, Result};
+use serde_json::{json, Value};
+use spin_sdk::{
+    http::Request,
+    wit::wasi::http0_2_0::{
+        outgoing_handler,
+        types::{Fields, Method, OutgoingBody, OutgoingRequest, RequestOptions, Scheme},
+    },
+};
+use url::Url;
+
+use crate::validator::{Observation, SpanRecord};
+
+const TRACEPARENT: &str = "traceparent";
+// base URL of the OTLP/HTTP collector (e.g. http://localhost:4318), it's also the host
+// allowed for outbound requests in spin.toml
+const VARIABLE_OTLP_ENDPOINT: &str = "otlp_endpoint";
+// spans are only exported if set to true
+const VARIABLE_OTLP_ENABLED: &str = "otlp_enabled";
+// share of requests without a traceparent which start a sampled trace, between 0 and 1
+const VARIABLE_OTLP_SAMPLE_RATIO: &str = "otlp_sample_ratio";
+const DEFAULT_SAMPLE_RATIO: f64 = 0.1;
+// bound of connecting to, and of waiting for, the collector
+const EXPORT_TIMEOUT: Duration = Duration::from_millis(250);
+const SERVICE_NAME: &str = "cat-validator";
+
+// OTLP span kinds and status codes
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+/// W3C trace context of a validation request
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    // span of the caller, if the request carried a traceparent
+    parent_id: Option<[u8; 8]>,
+    // span covering the entire validation
+    span_id: [u8; 8],
+    sampled: bool,
+}
+
+impl TraceContext {
+    // continues the trace of the caller, or starts a new one
+    pub fn from_request(req: &Request) -> Self {
+        let inbound = req
+            .header(TRACEPARENT)
+            .and_then(|value| value.as_str())
+            .and_then(parse_traceparent);
+        match inbound {
+            Some((trace_id, parent_id, sampled)) => Self {
+                trace_id,
+                parent_id: Some(parent_id),
+                span_id: random_id(),
+                sampled,
+            },
+            None => Self {
+                trace_id: random_id(),
+                parent_id: None,
+                span_id: random_id(),
+                sampled: sampled(sample_ratio()),
+            },
+        }
+    }
+
+    // traceparent referencing the validation span, returned to the caller
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            if self.sampled { "01" } else { "00" }
+        )
+    }
+}
+
+// exports the validation span and the spans of the pipeline steps to the collector
+pub fn export(
+    ctx: &TraceContext,
+    name: &str,
+    start: SystemTime,
+    duration: Duration,
+    observation: &Observation,
+) -> Result<()> {
+    if !ctx.sampled {
+        return Ok(());
+    }
+    if !spin_sdk::variables::get(VARIABLE_OTLP_ENABLED).is_ok_and(|enabled| enabled == "true") {
+        return Ok(());
+    }
+    let endpoint = spin_sdk::variables::get(VARIABLE_OTLP_ENDPOINT)?;
+    let endpoint = endpoint.trim_end_matches('/');
+    let mut spans = vec![span(
+        ctx,
+        ctx.span_id,
+        ctx.parent_id,
+        SPAN_KIND_SERVER,
+        name,
+        start,
+        duration,
+        observation.failure.is_some(),
+        vec![attribute(
+            "cat.outcome",
+            json!({"stringValue": observation
+                .failure
+                .map_or("success", |code| code.as_str())}),
+        )],
+    )];
+    spans.extend(observation.spans.iter().map(|record| step(ctx, record)));
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", json!({"stringValue": SERVICE_NAME}))]
+            },
+            "scopeSpans": [{
+                "scope": {"name": SERVICE_NAME},
+                "spans": spans
+            }]
+        }]
+    });
+    let status = post(
+        &format!("{endpoint}/v1/traces"),
+        body.to_string().as_bytes(),
+    )?;
+    match status {
+        200..=299 => Ok(()),
+        status => Err(Error::msg(format!(
+            "Collector rejected spans with status {status}"
+        ))),
+    }
+}
+
+// the export runs before the validation response is sent, so the request to the collector is
+// bounded by EXPORT_TIMEOUT (spin_sdk::http::send doesn't take request options)
+fn post(url: &str, body: &[u8]) -> Result<u16> {
+    let url = Url::parse(url).with_context(|| "Invalid OTLP endpoint")?;
+    let headers =
+        Fields::from_list(&[(String::from("content-type"), b"application/json".to_vec())])
+            .map_err(|e| Error::msg(format!("{e:?}")))?;
+    let request = OutgoingRequest::new(headers);
+    let scheme = match url.scheme() {
+        "http" => Scheme::Http,
+        "https" => Scheme::Https,
+        other => Scheme::Other(other.to_string()),
+    };
+    let authority = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(Error::msg("Invalid OTLP endpoint")),
+    };
+    let invalid = |_| Error::msg("Invalid OTLP request");
+    request.set_method(&Method::Post).map_err(invalid)?;
+    request.set_scheme(Some(&scheme)).map_err(invalid)?;
+    request.set_authority(Some(&authority)).map_err(invalid)?;
+    request
+        .set_path_with_query(Some(url.path()))
+        .map_err(invalid)?;
+
+    let timeout = Some(EXPORT_TIMEOUT.as_nanos() as u64);
+    let options = RequestOptions::new();
+    options.set_connect_timeout(timeout).map_err(invalid)?;
+    options.set_first_byte_timeout(timeout).map_err(invalid)?;
+    options
+        .set_between_bytes_timeout(timeout)
+        .map_err(invalid)?;
+
+    let outgoing_body = request.body().map_err(invalid)?;
+    let response = outgoing_handler::handle(request, Some(options))
+        .map_err(|e| Error::msg(format!("Error exporting spans ({e:?})")))?;
+    {
+        let stream = outgoing_body.write().map_err(invalid)?;
+        // a single blocking write is limited to 4096 bytes
+        for chunk in body.chunks(4096) {
+            stream
+                .blocking_write_and_flush(chunk)
+                .map_err(|e| Error::msg(format!("Error exporting spans ({e:?})")))?;
+        }
+    }
+    OutgoingBody::finish(outgoing_body, None)
+        .map_err(|e| Error::msg(format!("Error exporting spans ({e:?})")))?;
+    response.subscribe().block();
+    match response.get() {
+        Some(Ok(Ok(response))) => Ok(response.status()),
+        Some(Ok(Err(e))) => Err(Error::msg(format!("Error exporting spans ({e:?})"))),
+        _ => Err(Error::msg("Error exporting spans")),
+    }
+}
+
+fn step(ctx: &TraceContext, record: &SpanRecord) -> Value {
+    let mut attributes = vec![attribute(
+        "cat.stage",
+        json!({"stringValue": record.stage.as_str()}),
+    )];
+    if let Some(claim) = record.claim {
+        attributes.push(attribute("cat.claim", json!({"intValue": claim})));
+    }
+    span(
+        ctx,
+        random_id(),
+        Some(ctx.span_id),
+        SPAN_KIND_INTERNAL,
+        &record.name,
+        record.start,
+        record.duration,
+        record.failed,
+        attributes,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn span(
+    ctx: &TraceContext,
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    kind: u8,
+    name: &str,
+    start: SystemTime,
+    duration: Duration,
+    failed: bool,
+    attributes: Vec<Value>,
+) -> Value {
+    let start_nanos = start
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    json!({
+        "traceId": hex(&ctx.trace_id),
+        "spanId": hex(&span_id),
+        "parentSpanId": parent_id.map(|id| hex(&id)).unwrap_or_default(),
+        "name": name,
+        "kind": kind,
+        "startTimeUnixNano": start_nanos.to_string(),
+        "endTimeUnixNano": (start_nanos + duration.as_nanos()).to_string(),
+        "attributes": attributes,
+        "status": {"code": if failed { STATUS_ERROR } else { STATUS_OK }},
+    })
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({"key": key, "value": value})
+}
+
+// 00-<trace id>-<parent id>-<flags>, all-zero ids are invalid
+fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], bool)> {
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    let [version, trace_id, parent_id, flags] = parts.as_slice() else {
+        return None;
+    };
+    if *version != "00" {
+        return None;
+    }
+    let trace_id: [u8; 16] = unhex(trace_id)?.try_into().ok()?;
+    let parent_id: [u8; 8] = unhex(parent_id)?.try_into().ok()?;
+    let flags = unhex(flags)?;
+    if flags.len() != 1 || trace_id.iter().all(|b| *b == 0) || parent_id.iter().all(|b| *b == 0) {
+        return None;
+    }
+    Some((trace_id, parent_id, flags[0] & 1 == 1))
+}
+
+fn sample_ratio() -> f64 {
+    spin_sdk::variables::get(VARIABLE_OTLP_SAMPLE_RATIO)
+        .ok()
+        .and_then(|ratio| ratio.parse::<f64>().ok())
+        .unwrap_or(DEFAULT_SAMPLE_RATIO)
+}
+
+fn sampled(ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    if ratio <= 0.0 {
+        return false;
+    }
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).is_ok()
+        && (u64::from_le_bytes(bytes) as f64 / u64::MAX as f64) < ratio
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0u8; N];
+    // a failing random source degrades to a fixed (but valid) id
+    if getrandom::getrandom(&mut id).is_err() || id.iter().all(|b| *b == 0) {
+        id[N - 1] = 1;
+    }
+    id
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_traceparent() {
+        let (trace_id, parent_id, sampled) =
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(hex(&trace_id), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(hex(&parent_id), "00f067aa0ba902b7");
+        assert!(sampled);
+        let (_, _, sampled) =
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").unwrap();
+        assert!(!sampled);
+    }
+
+    #[test]
+    fn rejects_invalid_traceparent() {
+        for value in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+            "00-4bf92f3577b34da6a3ce929d0e0e473g-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+        ] {
+            assert!(parse_traceparent(value).is_none(), "{value}");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Error;
+use common_access_token::{cat_keys, CborValue};
+
+use crate::validator::{Convert, Validate};
+
+pub struct CatAlpnValidator {
+    // ALPN protocol negotiated for the request (e.g. h2, h3, http/1.1)
+    pub alpn: Option<String>,
+}
+
+impl Validate for CatAlpnValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &cat_keys::CATALPN
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> anyhow::Result<()> {
+        let allowed_protocols = match claim {
+            None => return Ok(()), // no alpn claim present
+            Some(CborValue::Text(protocol)) => vec![protocol.as_str()],
+            Some(CborValue::Array(protocols)) => protocols
+                .iter()
+                .map(|protocol| protocol.as_str())
+                .collect::<Option<Vec<&str>>>()
+                .ok_or(Error::msg(
+                    "Invalid format for CATALPN was expecting an Array of Text",
+                ))?,
+            _ => {
+                return Err(Error::msg(
+                    "Invalid format for CATALPN was expecting Text or an Array",
+                ))
+            }
+        };
+        let Some(alpn) = &self.alpn else {
+            return Err(Error::msg(
+                "Negotiated ALPN protocol required by claim (CATALPN) not presented",
+            ));
+        };
+        match allowed_protocols.contains(&alpn.trim()) {
+            true => Ok(()),
+            false => Err(Error::msg(format!(
+                "ALPN protocol {alpn} not granted by claim (CATALPN)"
+            ))),
+        }
+    }
+}
@@ -1,90 +1,248 @@
+use std::{
+    cell::{Cell, OnceCell},
+    rc::Rc,
+    time::{Instant, SystemTime},
+};
+
 use anyhow::{Context, Error, Result};
-use common_access_token::{Token, VerificationOptions};
+use common_access_token::{current_timestamp, Token, VerificationOptions};
 
 use crate::{
-    persistence::Persistence,
-    validator::{kv::KvValidator, Validate},
+    geoip::ClientLocation,
+    persistence::{BlockedClaimType, Issuers, Persistence, SessionPolicy, UnknownLocationPolicy},
+    validator::{
+        catif::CatIfEvaluator,
+        issuer::IssuerValidator,
+        kv::{BlocklistValidator, RevocationValidator},
+        pipeline::SyncValidator,
+        pop::PopValidator,
+        replay::CatReplayValidator,
+        session::SessionValidator,
+        time::TimeValidator,
+        AsyncValidate, BlockedError, CatCritValidator, FailureCode, Headers, Observation,
+        RequestContext, SpanRecord, Stage, TimeValidationOptions, Validate, SUPPORTED_CLAIMS,
+    },
 };
 
 pub struct CatValidationOptions {
     pub sync_validators: Vec<Box<dyn Validate>>,
+    pub async_validators: Vec<Box<dyn AsyncValidate>>,
     pub url: String,
     pub method: String,
-    pub issuer: String,
-    // refactor into kv sub struct
-    pub country: Option<String>,
+    pub headers: Headers,
+    pub location: Rc<ClientLocation>,
+    pub unknown_location: UnknownLocationPolicy,
     pub client_ip: String,
-    pub user_agent: Option<String>,
+    // nonce a DPoP proof has to carry
+    pub proof_nonce: Option<String>,
 
-    pub validate_expiration: bool,
-    pub validate_not_before: bool,
+    pub time: TimeValidationOptions,
     pub audience: Option<String>,
-    pub skip_kv_validations: bool,
+    // accepted audiences of the profile, any audience is accepted if empty
+    pub audiences: Vec<String>,
+    // checks backed by the key-value store
+    pub revocation: bool,
+    pub blocklists: Vec<BlockedClaimType>,
+    pub replay_protection: bool,
+    pub sessions: Option<SessionPolicy>,
 }
 
 pub struct Cat<'a> {
-    key: &'a str,
+    issuers: &'a Issuers,
+    // scope (default or tenant) of the data backing the pipeline
+    persistence: &'a Persistence,
 }
 impl<'a> Cat<'a> {
-    pub fn new(key: &'a str) -> Cat<'a> {
-        Cat { key }
+    pub fn new(issuers: &'a Issuers, persistence: &'a Persistence) -> Cat<'a> {
+        Cat {
+            issuers,
+            persistence,
+        }
     }
 
-    pub async fn validate(&self, cat: &[u8], opts: CatValidationOptions) -> Result<()> {
-        let token = Token::from_bytes(cat).with_context(|| "Token Decoding Failed")?;
+    pub async fn validate(
+        &self,
+        cat: &[u8],
+        opts: CatValidationOptions,
+        observation: &mut Observation,
+    ) -> Result<()> {
+        let token = observation.run("decode", Stage::Decode, FailureCode::Malformed, || {
+            Token::from_bytes(cat).with_context(|| "Token Decoding Failed")
+        })?;
+        // the policy is selected by the (not yet verified) iss claim of the token
+        let policy = observation.run(
+            "issuer lookup",
+            Stage::Verify,
+            FailureCode::UnknownIssuer,
+            || {
+                token
+                    .claims
+                    .registered
+                    .iss
+                    .as_ref()
+                    .and_then(|iss| self.issuers.find(iss))
+                    .ok_or_else(|| Error::msg("Issuer not trusted"))
+            },
+        )?;
+        let issuer_validator = IssuerValidator::from(policy);
+        observation.kid = Some(issuer_validator.key_label(&token));
+        observation.run(
+            "verify signature",
+            Stage::Verify,
+            FailureCode::InvalidSignature,
+            || issuer_validator.verify_signature(&token),
+        )?;
+        // identities are only reported for tokens with a valid signature, anyone can put
+        // arbitrary values into a forged token
+        let registered = &token.claims.registered;
+        observation.issuer = registered.iss.clone();
+        observation.subject = registered.sub.clone();
+        observation.cti = registered.cti.as_ref().map(base64_url::encode);
 
-        token
-            .verify(self.key.as_bytes())
-            .with_context(|| "Token Signature Validation Failed")?;
+        let ctx = RequestContext {
+            client_ip: &opts.client_ip,
+            url: &opts.url,
+            method: &opts.method,
+            headers: &opts.headers,
+            claims: &token.claims,
+            store: OnceCell::new(),
+            replayed: Cell::new(false),
+            persistence: self.persistence,
+        };
+        // revoked tokens are rejected right after their signature has been verified
+        if opts.revocation {
+            run_step(&RevocationValidator {}, &ctx, observation).await?;
+        }
 
-        if !opts.skip_kv_validations {
-            let blocking_data = match Persistence::get_blocking_data() {
-                Ok(data) => data,
-                Err(e) => {
-                    // we have to discuss how we should deal with this error
-                    return Err(e);
-                }
-            };
-            if blocking_data.any {
-                let kv_validator = KvValidator::from(blocking_data);
-                if kv_validator.is_subject_blocked(&token.claims.registered.sub, true) {
-                    return Err(Error::msg("Subject blocked"));
-                }
-                if opts.country.is_some() && kv_validator.is_country_blocked(&opts.country.unwrap())
-                {
-                    return Err(Error::msg("Country or Region blocked"));
-                }
+        // blocklists run first, the proof of possession, catreplay and sessions last, so that
+        // jti and cti are only consumed by otherwise valid requests
+        let mut pipeline: Vec<Box<dyn AsyncValidate>> = vec![];
+        if !opts.blocklists.is_empty() {
+            pipeline.push(Box::new(BlocklistValidator {
+                categories: opts.blocklists,
+                location: opts.location,
+                unknown_location: opts.unknown_location,
+            }));
+        }
+        pipeline.extend(
+            opts.sync_validators
+                .into_iter()
+                .map(|v| Box::new(SyncValidator(v)) as Box<dyn AsyncValidate>),
+        );
+        pipeline.extend(opts.async_validators);
+        pipeline.push(Box::new(PopValidator {
+            nonce: opts.proof_nonce,
+            check_replay: opts.replay_protection,
+        }));
+        pipeline.push(Box::new(CatReplayValidator {
+            track_uses: opts.replay_protection,
+        }));
+        // sessions are only started or kept alive by entirely valid requests
+        if let Some(policy) = opts.sessions {
+            pipeline.push(Box::new(SessionValidator { policy }));
+        }
+
+        // unknown critical claims are rejected before any other claim is looked at
+        let crit_validator = CatCritValidator {
+            understood_claims: SUPPORTED_CLAIMS
+                .into_iter()
+                .chain(pipeline.iter().filter_map(|v| v.get_claim_key().copied()))
+                .collect(),
+        };
+        observation
+            .run(
+                "crit",
+                Stage::Claims,
+                FailureCode::UnknownCriticalClaim,
+                || crit_validator.validate(token.claims.custom.get(crit_validator.get_claim_key())),
+            )
+            .inspect_err(|_| observation.claim = Some(*crit_validator.get_claim_key()))?;
+
+        observation.run("time", Stage::Claims, FailureCode::InvalidTime, || {
+            TimeValidator::from(opts.time.with_policy(policy))
+                .validate(&token.claims.registered, current_timestamp())
+        })?;
+        observation.run(
+            "issuer policy",
+            Stage::Claims,
+            FailureCode::IssuerPolicy,
+            || issuer_validator.validate_claims(&token),
+        )?;
 
-                if opts.user_agent.is_some()
-                    && kv_validator.is_user_agent_blocked(&opts.user_agent.unwrap())
+        observation.run(
+            "audience",
+            Stage::Claims,
+            FailureCode::AudienceRejected,
+            || {
+                if !opts.audiences.is_empty()
+                    && !token
+                        .claims
+                        .registered
+                        .aud
+                        .as_ref()
+                        .is_some_and(|aud| opts.audiences.contains(aud))
                 {
-                    return Err(Error::msg("User Agent blocked"));
+                    return Err(Error::msg("Audience not accepted by profile"));
                 }
-
-                if kv_validator.is_ip_blocked(&opts.client_ip) {
-                    return Err(Error::msg("IP address is blocked"));
+                if let Some(audience) = opts.audience {
+                    let options = VerificationOptions::new()
+                        .verify_exp(false)
+                        .verify_nbf(false)
+                        .require_aud(true)
+                        .expected_audience(audience);
+                    token.verify_claims(&options)?;
                 }
-            }
-        }
+                Ok(())
+            },
+        )?;
 
-        let options = VerificationOptions::new()
-            .verify_exp(opts.validate_expiration)
-            .verify_nbf(opts.validate_not_before)
-            .expected_issuer(opts.issuer.clone())
-            .verify_catu(true)
-            .require_aud(opts.audience.is_some())
-            .expected_audience(opts.audience.unwrap_or_default())
-            .uri(opts.url.clone())
-            .verify_catm(true)
-            .http_method(opts.method.clone().to_uppercase());
-        token.verify_claims(&options)?;
+        let catif = observation.run("catif", Stage::Claims, FailureCode::InvalidClaim, || {
+            CatIfEvaluator::from_claims(&token.claims.custom)
+        })?;
 
-        for v in opts.sync_validators {
-            let claim_value = token.claims.custom.get(v.get_claim_key());
-            if let Err(validation_error) = v.validate(claim_value) {
-                return Err(validation_error);
+        for v in pipeline {
+            if let Err(e) = run_step(v.as_ref(), &ctx, observation).await {
+                return Err(match v.get_claim_key() {
+                    Some(claim_key) => catif.on_failure(*claim_key, e),
+                    None => e,
+                });
             }
         }
         Ok(())
     }
 }
+
+// runs a step of the pipeline and records its span and failure
+async fn run_step(
+    v: &dyn AsyncValidate,
+    ctx: &RequestContext<'_>,
+    observation: &mut Observation,
+) -> Result<()> {
+    let (start, started) = (SystemTime::now(), Instant::now());
+    let result = v.validate(ctx).await;
+    observation.replayed |= ctx.replayed.get();
+    observation.record(SpanRecord {
+        name: match v.get_claim_key() {
+            Some(claim_key) => format!("claim {claim_key}"),
+            None => v.stage().as_str().to_string(),
+        },
+        stage: v.stage(),
+        start,
+        duration: started.elapsed(),
+        claim: v.get_claim_key().copied(),
+        failed: result.is_err(),
+    });
+    if let Err(e) = &result {
+        observation.failure = Some(match v.stage() {
+            Stage::Revocation => FailureCode::Revoked,
+            Stage::Blocklist => FailureCode::Blocked,
+            Stage::RateLimit => FailureCode::RateLimited,
+            Stage::Proof => FailureCode::InvalidProof,
+            Stage::Session => FailureCode::SessionLimit,
+            _ => FailureCode::InvalidClaim,
+        });
+        observation.claim = v.get_claim_key().copied();
+        observation.blocked = e.downcast_ref::<BlockedError>().map(|b| b.category);
+    }
+    result
+}
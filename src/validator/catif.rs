@@ -0,0 +1,130 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{Error, Result};
+use common_access_token::{cat_keys, CborValue};
+
+use crate::validator::Convert;
+
+/// Response requested by the token (catif) for a failed claim
+#[derive(Debug)]
+pub struct ConditionalResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub reason: String,
+}
+
+impl fmt::Display for ConditionalResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ConditionalResponse {}
+
+pub struct CatIfEvaluator {
+    // claim key -> (status, headers)
+    conditions: BTreeMap<i32, (u16, Vec<(String, String)>)>,
+}
+
+impl CatIfEvaluator {
+    // catif: {claim key: [status, {1: name, 2: value, 3: name, 4: value, ...}]}
+    // header values are Text or an Array of Text and Integer (index into catifdata) which is concatenated
+    pub fn from_claims(claims: &BTreeMap<i32, CborValue>) -> Result<Self> {
+        let conditions = match claims.get(&cat_keys::CATIF) {
+            None => BTreeMap::new(), // no catif claim present
+            Some(CborValue::Map(conditions)) => {
+                let data = match claims.get(&cat_keys::CATIFDATA) {
+                    None => vec![],
+                    Some(CborValue::Text(data)) => vec![data.as_str()],
+                    Some(CborValue::Array(data)) => data
+                        .iter()
+                        .map(|value| value.as_str())
+                        .collect::<Option<Vec<&str>>>()
+                        .ok_or(Error::msg(
+                            "Invalid format for CATIFDATA was expecting an Array of Text",
+                        ))?,
+                    _ => {
+                        return Err(Error::msg(
+                            "Invalid format for CATIFDATA was expecting Text or an Array",
+                        ))
+                    }
+                };
+                conditions
+                    .iter()
+                    .map(|(claim, condition)| {
+                        Self::as_condition(condition, &data)
+                            .map(|condition| (*claim, condition))
+                            .ok_or(Error::msg(format!(
+                                "Invalid condition for claim {claim} specified as part of CATIF"
+                            )))
+                    })
+                    .collect::<Result<_>>()?
+            }
+            _ => return Err(Error::msg("Invalid format for CATIF was expecting a Map")),
+        };
+        Ok(Self { conditions })
+    }
+
+    fn as_condition(value: &CborValue, data: &[&str]) -> Option<(u16, Vec<(String, String)>)> {
+        let CborValue::Array(values) = value else {
+            return None;
+        };
+        let (status, headers) = match values.as_slice() {
+            [status] => (status, None),
+            [status, headers, ..] => (status, Some(headers)),
+            _ => return None,
+        };
+        // a token must not turn a failed claim into a successful response
+        let status = status
+            .as_i64()
+            .and_then(|status| u16::try_from(status).ok())
+            .filter(|status| (300..=599).contains(status))?;
+        let headers = match headers {
+            None => vec![],
+            Some(CborValue::Map(headers)) if headers.len() % 2 == 0 => (1..=headers.len() as i32)
+                .step_by(2)
+                .map(|i| {
+                    let name = headers.get(&i)?.as_string()?;
+                    let value = Self::as_header_value(headers.get(&(i + 1))?, data)?;
+                    let valid = !name.is_empty()
+                        && name
+                            .bytes()
+                            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+                        && !value.bytes().any(|c| c.is_ascii_control());
+                    valid.then_some((name, value))
+                })
+                .collect::<Option<Vec<_>>>()?,
+            Some(_) => return None,
+        };
+        Some((status, headers))
+    }
+
+    fn as_header_value(value: &CborValue, data: &[&str]) -> Option<String> {
+        match value {
+            CborValue::Text(value) => Some(value.clone()),
+            CborValue::Array(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    CborValue::Text(part) => Some(part.as_str()),
+                    CborValue::Integer(idx) => usize::try_from(*idx)
+                        .ok()
+                        .and_then(|idx| data.get(idx).copied()),
+                    _ => None,
+                })
+                .collect::<Option<String>>(),
+            _ => None,
+        }
+    }
+
+    // replaces the error of a failed claim with the response requested by the token
+    pub fn on_failure(&self, claim: i32, e: Error) -> Error {
+        match self.conditions.get(&claim) {
+            None => e,
+            Some((status, headers)) => Error::new(ConditionalResponse {
+                status: *status,
+                headers: headers.clone(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+}
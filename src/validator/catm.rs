@@ -0,0 +1,82 @@
+use anyhow::{Error, Result};
+use common_access_token::{cat_keys, CborValue};
+
+use crate::validator::{Convert, Validate};
+
+const KNOWN_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+pub struct CatMethodValidator {
+    pub method: String,
+    // method announced by a CORS preflight (Access-Control-Request-Method)
+    pub preflight_method: Option<String>,
+    // HEAD requests are granted by GET
+    pub head_implied_by_get: bool,
+    // OPTIONS preflight requests are granted by the method they announce
+    pub allow_preflight: bool,
+}
+
+impl CatMethodValidator {
+    fn is_granted(&self, method: &str, allowed: &[String]) -> bool {
+        let granted = |m: &str| allowed.iter().any(|a| a == m);
+        match method {
+            m if granted(m) => true,
+            "HEAD" => self.head_implied_by_get && granted("GET"),
+            "OPTIONS" if self.allow_preflight => self
+                .preflight_method
+                .as_ref()
+                .map(|m| m.trim().to_uppercase())
+                .is_some_and(|m| m != "OPTIONS" && self.is_granted(&m, allowed)),
+            _ => false,
+        }
+    }
+}
+
+impl Validate for CatMethodValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &cat_keys::CATM
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        let allowed = match claim {
+            None => return Ok(()), // no method claim present
+            Some(CborValue::Text(method)) => vec![method.as_str()],
+            Some(CborValue::Array(methods)) => methods
+                .iter()
+                .map(|method| method.as_str())
+                .collect::<Option<Vec<&str>>>()
+                .ok_or(Error::msg(
+                    "Invalid format for CATM was expecting an Array of Text",
+                ))?,
+            _ => {
+                return Err(Error::msg(
+                    "Invalid format for CATM was expecting Text or an Array",
+                ))
+            }
+        };
+        let allowed = allowed
+            .iter()
+            .map(|method| method.trim().to_uppercase())
+            .collect::<Vec<String>>();
+        if allowed.is_empty() {
+            return Err(Error::msg("No HTTP methods specified as part of CATM"));
+        }
+        if let Some(unknown) = allowed
+            .iter()
+            .find(|method| !KNOWN_METHODS.contains(&method.as_str()))
+        {
+            return Err(Error::msg(format!(
+                "Unknown HTTP method {unknown} specified as part of CATM"
+            )));
+        }
+        let method = self.method.trim().to_uppercase();
+        match self.is_granted(&method, &allowed) {
+            true => Ok(()),
+            false => Err(Error::msg(format!(
+                "HTTP method {method} not granted by claim (CATM), allowed methods: {}",
+                allowed.join(", ")
+            ))),
+        }
+    }
+}
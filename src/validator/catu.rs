@@ -0,0 +1,300 @@
+use anyhow::{Error, Result};
+use common_access_token::{cat_keys, match_types, uri_components, CborValue};
+use sha2::{Digest, Sha256, Sha512_256};
+use url::Url;
+
+use crate::validator::{regex_cache, Validate};
+
+pub struct CatUriValidator {
+    pub url: String,
+}
+
+// the URI components defined by CTA-5007
+struct UriComponents {
+    scheme: String,
+    host: String,
+    port: String,
+    path: String,
+    query: String,
+    parent_path: String,
+    filename: String,
+    stem: String,
+    extension: String,
+}
+
+impl UriComponents {
+    fn parse(url: &str) -> Result<Self> {
+        let url = Url::parse(url).map_err(|_| Error::msg("Invalid request URL received"))?;
+        let path = url.path().to_string();
+        // /media/video.m3u8 -> parent path /media, filename video.m3u8
+        let (parent_path, filename) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+        // the extension includes the leading dot (.m3u8), a filename without a dot has no extension
+        let (stem, extension) = match filename.rsplit_once('.') {
+            Some((stem, extension)) => (stem, format!(".{extension}")),
+            None => (filename, String::new()),
+        };
+        Ok(Self {
+            scheme: url.scheme().to_string(),
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url
+                .port_or_known_default()
+                .map(|port| port.to_string())
+                .unwrap_or_default(),
+            query: url.query().unwrap_or_default().to_string(),
+            parent_path: parent_path.to_string(),
+            filename: filename.to_string(),
+            stem: stem.to_string(),
+            extension,
+            path,
+        })
+    }
+
+    fn get(&self, component: i32) -> Option<(&'static str, &str)> {
+        Some(match component {
+            uri_components::SCHEME => ("scheme", &self.scheme),
+            uri_components::HOST => ("host", &self.host),
+            uri_components::PORT => ("port", &self.port),
+            uri_components::PATH => ("path", &self.path),
+            uri_components::QUERY => ("query", &self.query),
+            uri_components::PARENT_PATH => ("parent-path", &self.parent_path),
+            uri_components::FILENAME => ("filename", &self.filename),
+            uri_components::STEM => ("stem", &self.stem),
+            uri_components::EXTENSION => ("extension", &self.extension),
+            _ => return None,
+        })
+    }
+}
+
+impl CatUriValidator {
+    fn matches(match_type: i32, expected: &CborValue, value: &str) -> Result<bool> {
+        Ok(match (match_type, expected) {
+            (match_types::EXACT, CborValue::Text(expected)) => value == expected,
+            (match_types::PREFIX, CborValue::Text(expected)) => value.starts_with(expected),
+            (match_types::SUFFIX, CborValue::Text(expected)) => value.ends_with(expected),
+            (match_types::CONTAINS, CborValue::Text(expected)) => value.contains(expected),
+            // [pattern, capture groups...]
+            (match_types::REGEX, CborValue::Array(regex)) => match regex.first() {
+                Some(CborValue::Text(pattern)) => regex_cache::compile(pattern)?.is_match(value),
+                _ => return Err(Error::msg("Invalid regular expression specified in CATU")),
+            },
+            (match_types::SHA256, CborValue::Bytes(expected)) => {
+                Sha256::digest(value.as_bytes()).as_slice() == expected.as_slice()
+            }
+            (match_types::SHA512_256, CborValue::Bytes(expected)) => {
+                Sha512_256::digest(value.as_bytes()).as_slice() == expected.as_slice()
+            }
+            _ => {
+                return Err(Error::msg(format!(
+                    "Unsupported match type {match_type} specified in CATU"
+                )))
+            }
+        })
+    }
+}
+
+impl Validate for CatUriValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &cat_keys::CATU
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        let components = match claim {
+            None => return Ok(()), // no uri claim present
+            Some(CborValue::Map(components)) => components,
+            _ => return Err(Error::msg("Invalid format for CATU was expecting a Map")),
+        };
+        let uri = UriComponents::parse(&self.url)?;
+        for (component, matches) in components {
+            let Some((name, value)) = uri.get(*component) else {
+                return Err(Error::msg(format!(
+                    "Unsupported URI component {component} specified in CATU"
+                )));
+            };
+            let CborValue::Map(matches) = matches else {
+                return Err(Error::msg(format!(
+                    "Invalid format for URI component {name} in CATU was expecting a Map"
+                )));
+            };
+            for (match_type, expected) in matches {
+                if !Self::matches(*match_type, expected, value)? {
+                    return Err(Error::msg(format!(
+                        "URI component {name} not granted by claim (CATU)"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(url: &str) -> UriComponents {
+        UriComponents::parse(url).unwrap()
+    }
+
+    fn claim(component: i32, match_type: i32, expected: CborValue) -> CborValue {
+        CborValue::Map([(component, CborValue::Map([(match_type, expected)].into()))].into())
+    }
+
+    fn validate(url: &str, claim: CborValue) -> Result<()> {
+        CatUriValidator {
+            url: url.to_string(),
+        }
+        .validate(Some(&claim))
+    }
+
+    fn text(value: &str) -> CborValue {
+        CborValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn splits_paths() {
+        let uri = components("https://example.com/media/video.m3u8?token=1");
+        assert_eq!(uri.path, "/media/video.m3u8");
+        assert_eq!(uri.parent_path, "/media");
+        assert_eq!(uri.filename, "video.m3u8");
+        assert_eq!(uri.stem, "video");
+        assert_eq!(uri.extension, ".m3u8");
+        assert_eq!(uri.query, "token=1");
+
+        let uri = components("https://example.com/");
+        assert_eq!(uri.parent_path, "");
+        assert_eq!(uri.filename, "");
+
+        let uri = components("https://example.com/a");
+        assert_eq!(uri.parent_path, "");
+        assert_eq!(uri.filename, "a");
+    }
+
+    #[test]
+    fn extensions() {
+        let uri = components("https://example.com/media/archive.tar.gz");
+        assert_eq!(
+            (uri.stem.as_str(), uri.extension.as_str()),
+            ("archive.tar", ".gz")
+        );
+        // no dot, no extension
+        let uri = components("https://example.com/media/video");
+        assert_eq!((uri.stem.as_str(), uri.extension.as_str()), ("video", ""));
+        // a trailing slash leaves an empty filename
+        let uri = components("https://example.com/media/");
+        assert_eq!(uri.parent_path, "/media");
+        assert_eq!((uri.filename.as_str(), uri.extension.as_str()), ("", ""));
+    }
+
+    #[test]
+    fn default_ports() {
+        assert_eq!(components("https://example.com/").port, "443");
+        assert_eq!(components("http://example.com/").port, "80");
+        assert_eq!(components("https://example.com:8443/").port, "8443");
+        assert!(validate(
+            "https://example.com/",
+            claim(uri_components::PORT, match_types::EXACT, text("443"))
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn hash_matches() {
+        let url = "https://example.com/media/video.m3u8";
+        let host = Sha256::digest(b"example.com").to_vec();
+        assert!(validate(
+            url,
+            claim(
+                uri_components::HOST,
+                match_types::SHA256,
+                CborValue::Bytes(host.clone())
+            )
+        )
+        .is_ok());
+        let path = Sha512_256::digest(b"/media/video.m3u8").to_vec();
+        assert!(validate(
+            url,
+            claim(
+                uri_components::PATH,
+                match_types::SHA512_256,
+                CborValue::Bytes(path)
+            )
+        )
+        .is_ok());
+        // the digest of a different component doesn't match
+        assert!(validate(
+            url,
+            claim(
+                uri_components::PATH,
+                match_types::SHA256,
+                CborValue::Bytes(host)
+            )
+        )
+        .is_err());
+        // SHA-512/256 differs from a truncated SHA-512
+        let truncated = sha2::Sha512::digest(b"example.com")[..32].to_vec();
+        assert!(validate(
+            url,
+            claim(
+                uri_components::HOST,
+                match_types::SHA512_256,
+                CborValue::Bytes(truncated)
+            )
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn matches_each_component() {
+        let url = "https://cdn.example.com/media/video.m3u8?session=1";
+        let cases = [
+            (uri_components::SCHEME, "https", "http"),
+            (uri_components::HOST, "cdn.example.com", "example.com"),
+            (uri_components::PORT, "443", "80"),
+            (uri_components::PATH, "/media/video.m3u8", "/media/video.ts"),
+            (uri_components::QUERY, "session=1", "session=2"),
+            (uri_components::PARENT_PATH, "/media", "/other"),
+            (uri_components::FILENAME, "video.m3u8", "video.ts"),
+            (uri_components::STEM, "video", "audio"),
+            (uri_components::EXTENSION, ".m3u8", "m3u8"),
+        ];
+        for (component, granted, denied) in cases {
+            assert!(
+                validate(url, claim(component, match_types::EXACT, text(granted))).is_ok(),
+                "{component}"
+            );
+            assert!(
+                validate(url, claim(component, match_types::EXACT, text(denied))).is_err(),
+                "{component}"
+            );
+        }
+    }
+
+    #[test]
+    fn match_types() {
+        let url = "https://example.com/media/video.m3u8";
+        let path = |match_type, expected| claim(uri_components::PATH, match_type, expected);
+        assert!(validate(url, path(match_types::PREFIX, text("/media/"))).is_ok());
+        assert!(validate(url, path(match_types::PREFIX, text("/video"))).is_err());
+        assert!(validate(url, path(match_types::SUFFIX, text(".m3u8"))).is_ok());
+        assert!(validate(url, path(match_types::SUFFIX, text(".ts"))).is_err());
+        assert!(validate(url, path(match_types::CONTAINS, text("video"))).is_ok());
+        assert!(validate(url, path(match_types::CONTAINS, text("audio"))).is_err());
+        let regex = |pattern: &str| CborValue::Array(vec![text(pattern)]);
+        assert!(validate(url, path(match_types::REGEX, regex(r"^/media/\w+\.m3u8$"))).is_ok());
+        assert!(validate(url, path(match_types::REGEX, regex(r"^/other/"))).is_err());
+        // a text value for a hash match is malformed
+        assert!(validate(url, path(match_types::SHA256, text("/media/video.m3u8"))).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_components() {
+        let url = "https://example.com/";
+        assert!(validate(url, claim(99, match_types::EXACT, text("x"))).is_err());
+        assert!(validate(
+            url,
+            CborValue::Map([(uri_components::HOST, text("x"))].into())
+        )
+        .is_err());
+        assert!(validate(url, text("https://example.com/")).is_err());
+    }
+}
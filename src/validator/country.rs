@@ -1,10 +1,17 @@
+use std::rc::Rc;
+
 use anyhow::Error;
 use common_access_token::{cat_keys, CborValue};
 
-use crate::validator::{Convert, Validate};
+use crate::{
+    geoip::ClientLocation,
+    persistence::UnknownLocationPolicy,
+    validator::{iso3166::Region, Convert, Validate},
+};
 
 pub struct CatCountryValidator {
-    pub country: String,
+    pub location: Rc<ClientLocation>,
+    pub unknown_location: UnknownLocationPolicy,
 }
 
 impl Validate for CatCountryValidator {
@@ -27,14 +34,28 @@ impl Validate for CatCountryValidator {
                 ))
             }
         };
-        match allowed_countries
+        let Some(country) = self.location.country()? else {
+            return match self.unknown_location {
+                UnknownLocationPolicy::Allow => Ok(()),
+                UnknownLocationPolicy::Deny => Err(Error::msg(
+                    "Request origin unknown but required by claim (CATGEOISO3166)",
+                )),
+            };
+        };
+        let granted = allowed_countries
             .iter()
-            .filter_map(|val| val.as_string())
-            .map(|country| country.to_uppercase().trim().to_string())
-            .collect::<Vec<String>>()
-            .contains(&self.country.to_uppercase().trim().to_string())
-        {
-            true => Ok(()), // country in granted by claim
+            .map(|val| val.as_str().and_then(Region::parse))
+            .collect::<Option<Vec<Region>>>()
+            .ok_or(Error::msg(
+                "Invalid ISO 3166 code specified as part of CATGEOISO3166",
+            ))?;
+        let Some(location) = Region::parse(country) else {
+            return Err(Error::msg(format!(
+                "Request origin {country} is not a valid ISO 3166 code"
+            )));
+        };
+        match granted.iter().any(|region| region.covers(&location)) {
+            true => Ok(()), // country or subdivision granted by claim
             false => Err(Error::msg(
                 "Request origin not granted by claim (CATGEOISO3166)",
             )),
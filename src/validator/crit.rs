@@ -0,0 +1,42 @@
+use anyhow::{Error, Result};
+use common_access_token::CborValue;
+
+use crate::validator::{claim_keys, Convert, Validate};
+
+pub struct CatCritValidator {
+    // claim keys the validator understands
+    pub understood_claims: Vec<i32>,
+}
+
+impl Validate for CatCritValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &claim_keys::CRIT
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        let critical_claims = match claim {
+            None => return Ok(()), // no crit claim present
+            Some(CborValue::Array(c)) if !c.is_empty() => c,
+            _ => {
+                return Err(Error::msg(
+                    "Invalid format for CRIT was expecting a non-empty Array",
+                ))
+            }
+        };
+        for critical in critical_claims {
+            let Some(key) = critical.as_i64() else {
+                return Err(Error::msg("Invalid claim key specified as part of CRIT"));
+            };
+            if !self
+                .understood_claims
+                .iter()
+                .any(|understood| *understood as i64 == key)
+            {
+                return Err(Error::msg(format!(
+                    "Critical claim {key} is not supported (CRIT)"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+
+use anyhow::{Error, Result};
+use common_access_token::CborValue;
+use serde_json::Value;
+
+use crate::{
+    persistence::{ClaimCondition, ClaimRule, ClaimType, CompareOperator, RequestField},
+    validator::{is_built_in_claim, regex_cache, Convert, Validate},
+};
+
+pub struct CustomClaimValidator {
+    pub claim_key: i32,
+    pub rule: ClaimRule,
+    // value of the request field referenced by a compare condition
+    pub field_value: Option<String>,
+}
+
+impl CustomClaimValidator {
+    // rules are checked when they're configured, so that they can't silently replace built-in
+    // validators and misconfigurations don't surface as validation failures only
+    pub fn verify_rule(claim_key: i32, rule: &ClaimRule) -> Result<()> {
+        if is_built_in_claim(claim_key) {
+            return Err(Error::msg(format!(
+                "claim {claim_key} is validated by the validator itself"
+            )));
+        }
+        let claim_type = rule.claim_type;
+        match &rule.condition {
+            Some(ClaimCondition::Range { min, max }) => {
+                if claim_type != ClaimType::Integer {
+                    return Err(Error::msg(format!(
+                        "range condition of claim {claim_key} requires type integer"
+                    )));
+                }
+                if min.zip(*max).is_some_and(|(min, max)| min > max) {
+                    return Err(Error::msg(format!(
+                        "range condition of claim {claim_key} is empty"
+                    )));
+                }
+            }
+            Some(ClaimCondition::Regex(pattern)) => {
+                if claim_type != ClaimType::Text {
+                    return Err(Error::msg(format!(
+                        "regex condition of claim {claim_key} requires type text"
+                    )));
+                }
+                regex_cache::compile(pattern)?;
+            }
+            Some(ClaimCondition::Compare { .. })
+                if !matches!(claim_type, ClaimType::Integer | ClaimType::Text) =>
+            {
+                return Err(Error::msg(format!(
+                    "compare condition of claim {claim_key} requires type integer or text"
+                )));
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn type_name(claim_type: ClaimType) -> &'static str {
+        match claim_type {
+            ClaimType::Integer => "Integer",
+            ClaimType::Text => "Text",
+            ClaimType::Bytes => "Bytes",
+            ClaimType::Array => "Array",
+            ClaimType::Map => "Map",
+        }
+    }
+
+    fn describe(condition: &ClaimCondition) -> String {
+        match condition {
+            ClaimCondition::Equals(expected) => format!("equal to {expected}"),
+            ClaimCondition::In(expected) => format!("one of {}", Value::from(expected.clone())),
+            ClaimCondition::Range { min, max } => format!(
+                "within [{}, {}]",
+                min.map_or("-".to_string(), |min| min.to_string()),
+                max.map_or("-".to_string(), |max| max.to_string())
+            ),
+            ClaimCondition::Regex(pattern) => format!("matching {pattern}"),
+            ClaimCondition::Compare { operator, field } => {
+                let operator = match operator {
+                    CompareOperator::Eq => "equal to",
+                    CompareOperator::Ne => "not equal to",
+                    CompareOperator::Lt => "less than",
+                    CompareOperator::Le => "less than or equal to",
+                    CompareOperator::Gt => "greater than",
+                    CompareOperator::Ge => "greater than or equal to",
+                };
+                let field = match field {
+                    RequestField::Method => "the request method".to_string(),
+                    RequestField::Url => "the request URL".to_string(),
+                    RequestField::ClientIp => "the client IP".to_string(),
+                    RequestField::Country => "the request country".to_string(),
+                    RequestField::Alpn => "the ALPN protocol".to_string(),
+                    RequestField::Header(name) => format!("HTTP Header {name}"),
+                };
+                format!("{operator} {field}")
+            }
+        }
+    }
+
+    fn equals(value: &CborValue, expected: &Value) -> bool {
+        match (value, expected) {
+            (CborValue::Integer(value), Value::Number(expected)) => {
+                expected.as_i64() == Some(*value)
+            }
+            (CborValue::Text(value), Value::String(expected)) => value == expected,
+            (CborValue::Bytes(value), Value::String(expected)) => {
+                base64_url::decode(expected).is_ok_and(|expected| expected == *value)
+            }
+            _ => false,
+        }
+    }
+
+    fn compare(&self, value: &CborValue, field_value: &str) -> Result<Ordering> {
+        match value {
+            CborValue::Integer(value) => field_value
+                .trim()
+                .parse::<i64>()
+                .map(|field_value| value.cmp(&field_value))
+                .map_err(|_| {
+                    Error::msg(format!(
+                        "Request value compared with claim {} is not an Integer",
+                        self.claim_key
+                    ))
+                }),
+            CborValue::Text(value) => Ok(value.as_str().cmp(field_value)),
+            _ => Err(Error::msg(format!(
+                "Claim {} can't be compared with a request value",
+                self.claim_key
+            ))),
+        }
+    }
+}
+
+impl Validate for CustomClaimValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &self.claim_key
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        let key = self.claim_key;
+        let Some(value) = claim else {
+            return Ok(()); // custom claim not present
+        };
+        let type_matches = matches!(
+            (self.rule.claim_type, value),
+            (ClaimType::Integer, CborValue::Integer(_))
+                | (ClaimType::Text, CborValue::Text(_))
+                | (ClaimType::Bytes, CborValue::Bytes(_))
+                | (ClaimType::Array, CborValue::Array(_))
+                | (ClaimType::Map, CborValue::Map(_))
+        );
+        if !type_matches {
+            return Err(Error::msg(format!(
+                "Invalid format for claim {key} was expecting {}",
+                Self::type_name(self.rule.claim_type)
+            )));
+        }
+        let Some(condition) = &self.rule.condition else {
+            return Ok(());
+        };
+        let valid = match condition {
+            ClaimCondition::Equals(expected) => Self::equals(value, expected),
+            ClaimCondition::In(expected) => expected
+                .iter()
+                .any(|expected| Self::equals(value, expected)),
+            ClaimCondition::Range { min, max } => {
+                let Some(value) = value.as_i64() else {
+                    return Err(Error::msg(format!(
+                        "Range condition of claim {key} requires an Integer"
+                    )));
+                };
+                min.map_or(true, |min| value >= min) && max.map_or(true, |max| value <= max)
+            }
+            ClaimCondition::Regex(pattern) => {
+                let Some(value) = value.as_str() else {
+                    return Err(Error::msg(format!(
+                        "Regex condition of claim {key} requires Text"
+                    )));
+                };
+                regex_cache::compile(pattern)?.is_match(value)
+            }
+            ClaimCondition::Compare { operator, .. } => {
+                let Some(field_value) = &self.field_value else {
+                    return Err(Error::msg(format!(
+                        "Request value compared with claim {key} not presented"
+                    )));
+                };
+                let ordering = self.compare(value, field_value)?;
+                match operator {
+                    CompareOperator::Eq => ordering.is_eq(),
+                    CompareOperator::Ne => ordering.is_ne(),
+                    CompareOperator::Lt => ordering.is_lt(),
+                    CompareOperator::Le => ordering.is_le(),
+                    CompareOperator::Gt => ordering.is_gt(),
+                    CompareOperator::Ge => ordering.is_ge(),
+                }
+            }
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(Error::msg(format!(
+                "Claim {key} must be {}",
+                Self::describe(condition)
+            ))),
+        }
+    }
+}
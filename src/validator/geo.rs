@@ -0,0 +1,326 @@
+use anyhow::{Error, Result};
+use common_access_token::{cat_keys, CborValue};
+
+use crate::validator::{claim_keys, Convert, Validate};
+
+// mean earth radius in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Clone, Copy)]
+pub struct Coordinate {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinate {
+    // great-circle distance in meters (haversine)
+    fn distance_to(&self, other: &Coordinate) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+
+    fn geohash(&self, precision: usize) -> String {
+        let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+        let mut hash = String::with_capacity(precision);
+        let mut even_bit = true;
+        let (mut bits, mut idx) = (0, 0_usize);
+        while hash.len() < precision {
+            let (range, value) = match even_bit {
+                true => (&mut lon_range, self.longitude),
+                false => (&mut lat_range, self.latitude),
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            idx <<= 1;
+            if value >= mid {
+                idx |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even_bit = !even_bit;
+            bits += 1;
+            if bits == 5 {
+                hash.push(GEOHASH_ALPHABET[idx] as char);
+                (bits, idx) = (0, 0);
+            }
+        }
+        hash
+    }
+}
+
+// CBOR has no float support in common_access_token, fractional values are carried as Text
+fn as_f64(value: &CborValue) -> Option<f64> {
+    match value {
+        CborValue::Integer(v) => Some(*v as f64),
+        CborValue::Text(v) => v.trim().parse::<f64>().ok().filter(|v| v.is_finite()),
+        _ => None,
+    }
+}
+
+pub struct CatGeoCoordValidator {
+    pub location: Option<Coordinate>,
+}
+
+impl CatGeoCoordValidator {
+    // [lat, long, radius in meters]
+    fn as_area(value: &CborValue) -> Option<(Coordinate, f64)> {
+        let CborValue::Array(values) = value else {
+            return None;
+        };
+        match values.as_slice() {
+            [lat, lon, radius] => Some((
+                Coordinate {
+                    latitude: as_f64(lat).filter(|lat| lat.abs() <= 90.0)?,
+                    longitude: as_f64(lon).filter(|lon| lon.abs() <= 180.0)?,
+                },
+                as_f64(radius).filter(|radius| *radius >= 0.0)?,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Validate for CatGeoCoordValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &cat_keys::CATGEOCOORD
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        let areas = match claim {
+            None => return Ok(()), // no coordinate claim present
+            // a single area or an array of areas
+            Some(value @ CborValue::Array(values)) => match Self::as_area(value) {
+                Some(area) => vec![area],
+                None => values
+                    .iter()
+                    .map(Self::as_area)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(Error::msg(
+                        "Invalid format for CATGEOCOORD was expecting [lat, long, radius]",
+                    ))?,
+            },
+            _ => {
+                return Err(Error::msg(
+                    "Invalid format for CATGEOCOORD was expecting an Array",
+                ))
+            }
+        };
+        let Some(location) = &self.location else {
+            return Err(Error::msg(
+                "Client coordinates required by claim (CATGEOCOORD) not presented",
+            ));
+        };
+        match areas
+            .iter()
+            .any(|(center, radius)| center.distance_to(location) <= *radius)
+        {
+            true => Ok(()),
+            false => Err(Error::msg(
+                "Client location not granted by claim (CATGEOCOORD)",
+            )),
+        }
+    }
+}
+
+pub struct CatGeohashValidator {
+    pub location: Option<Coordinate>,
+}
+
+impl Validate for CatGeohashValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &claim_keys::GEOHASH
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        let cells = match claim {
+            None => return Ok(()), // no geohash claim present
+            Some(CborValue::Text(cell)) => vec![cell.to_lowercase()],
+            Some(CborValue::Array(cells)) => cells
+                .iter()
+                .map(|cell| cell.as_string().map(|cell| cell.to_lowercase()))
+                .collect::<Option<Vec<String>>>()
+                .ok_or(Error::msg(
+                    "Invalid format for GEOHASH was expecting an Array of Text",
+                ))?,
+            _ => {
+                return Err(Error::msg(
+                    "Invalid format for GEOHASH was expecting Text or an Array",
+                ))
+            }
+        };
+        if cells
+            .iter()
+            .any(|cell| cell.is_empty() || !cell.bytes().all(|c| GEOHASH_ALPHABET.contains(&c)))
+        {
+            return Err(Error::msg(
+                "Invalid geohash cell specified as part of GEOHASH",
+            ));
+        }
+        let Some(location) = &self.location else {
+            return Err(Error::msg(
+                "Client coordinates required by claim (GEOHASH) not presented",
+            ));
+        };
+        match cells
+            .iter()
+            .any(|cell| location.geohash(cell.len()) == *cell)
+        {
+            true => Ok(()),
+            false => Err(Error::msg("Client location not granted by claim (GEOHASH)")),
+        }
+    }
+}
+
+pub struct CatGeoAltValidator {
+    // altitude in meters
+    pub altitude: Option<f64>,
+}
+
+impl Validate for CatGeoAltValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &cat_keys::CATGEOALT
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        // [min, max] altitude in meters
+        let (min, max) = match claim {
+            None => return Ok(()), // no altitude claim present
+            Some(CborValue::Array(values)) => match values.as_slice() {
+                [min, max] => (
+                    as_f64(min).ok_or(Error::msg("Invalid minimum altitude in CATGEOALT"))?,
+                    as_f64(max).ok_or(Error::msg("Invalid maximum altitude in CATGEOALT"))?,
+                ),
+                _ => {
+                    return Err(Error::msg(
+                        "Invalid format for CATGEOALT was expecting [min, max]",
+                    ))
+                }
+            },
+            _ => {
+                return Err(Error::msg(
+                    "Invalid format for CATGEOALT was expecting an Array",
+                ))
+            }
+        };
+        if min > max {
+            return Err(Error::msg(
+                "Invalid range in CATGEOALT, minimum exceeds maximum",
+            ));
+        }
+        let Some(altitude) = self.altitude else {
+            return Err(Error::msg(
+                "Client altitude required by claim (CATGEOALT) not presented",
+            ));
+        };
+        match (min..=max).contains(&altitude) {
+            true => Ok(()),
+            false => Err(Error::msg(
+                "Client altitude not granted by claim (CATGEOALT)",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> CborValue {
+        CborValue::Text(value.to_string())
+    }
+
+    fn area(lat: &str, lon: &str, radius: i64) -> CborValue {
+        CborValue::Array(vec![text(lat), text(lon), CborValue::Integer(radius)])
+    }
+
+    const BERLIN: Coordinate = Coordinate {
+        latitude: 52.52,
+        longitude: 13.405,
+    };
+    const PARIS: Coordinate = Coordinate {
+        latitude: 48.8566,
+        longitude: 2.3522,
+    };
+
+    #[test]
+    fn haversine_distance() {
+        let distance = BERLIN.distance_to(&PARIS);
+        assert!((distance - 877_464.5).abs() < 1.0, "{distance}");
+        assert_eq!(BERLIN.distance_to(&BERLIN), 0.0);
+    }
+
+    #[test]
+    fn geohash_of_known_point() {
+        let point = Coordinate {
+            latitude: 57.64911,
+            longitude: 10.40744,
+        };
+        assert_eq!(point.geohash(11), "u4pruydqqvj");
+        assert_eq!(point.geohash(5), "u4pru");
+    }
+
+    #[test]
+    fn single_area_and_list_of_areas() {
+        let validator = CatGeoCoordValidator {
+            location: Some(PARIS),
+        };
+        assert!(validator
+            .validate(Some(&area("48.85", "2.35", 5_000)))
+            .is_ok());
+        assert!(validator
+            .validate(Some(&area("52.52", "13.405", 5_000)))
+            .is_err());
+        // three areas must not be taken for a single [lat, long, radius]
+        let areas = CborValue::Array(vec![
+            area("52.52", "13.405", 5_000),
+            area("40.71", "-74.0", 5_000),
+            area("48.85", "2.35", 5_000),
+        ]);
+        assert!(validator.validate(Some(&areas)).is_ok());
+        assert!(validator
+            .validate(Some(&CborValue::Array(vec![text("48.85"), text("2.35")])))
+            .is_err());
+        assert!(validator
+            .validate(Some(&area("91", "2.35", 5_000)))
+            .is_err());
+    }
+
+    #[test]
+    fn geohash_cells() {
+        let validator = CatGeohashValidator {
+            location: Some(Coordinate {
+                latitude: 57.64911,
+                longitude: 10.40744,
+            }),
+        };
+        assert!(validator.validate(Some(&text("u4pru"))).is_ok());
+        assert!(validator.validate(Some(&text("U4PRU"))).is_ok());
+        assert!(validator.validate(Some(&text("u4prv"))).is_err());
+        assert!(validator.validate(Some(&text("u4pra"))).is_err());
+    }
+
+    #[test]
+    fn altitude_bounds() {
+        let range = |min: i64, max: i64| {
+            CborValue::Array(vec![CborValue::Integer(min), CborValue::Integer(max)])
+        };
+        let validator = CatGeoAltValidator {
+            altitude: Some(120.0),
+        };
+        assert!(validator.validate(Some(&range(100, 200))).is_ok());
+        assert!(validator.validate(Some(&range(120, 120))).is_ok());
+        assert!(validator.validate(Some(&range(121, 200))).is_err());
+        assert!(validator.validate(Some(&range(0, 119))).is_err());
+        assert!(validator.validate(Some(&range(200, 100))).is_err());
+        assert!(validator
+            .validate(Some(&CborValue::Array(vec![text("-10.5"), text("120.5")])))
+            .is_ok());
+        assert!(CatGeoAltValidator { altitude: None }
+            .validate(Some(&range(100, 200)))
+            .is_err());
+    }
+}
@@ -2,11 +2,81 @@ use anyhow::{Context, Error, Result};
 use std::collections::HashMap;
 
 use common_access_token::{cat_keys, CborValue};
+use serde::{Deserialize, Deserializer};
 
-use crate::validator::{Convert, Validate};
+use crate::{
+    persistence::HeaderMatchMode,
+    validator::{Convert, Validate},
+};
+
+/// Request headers, names are case-insensitive and may occur multiple times
+#[derive(Default, Clone)]
+pub struct Headers {
+    // (lowercase name, value) in the order they were received
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).next()
+    }
+
+    pub fn get_all(&self, name: &str) -> impl Iterator<Item = &str> {
+        let name = name.to_lowercase();
+        self.entries
+            .iter()
+            .filter(move |(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Headers {
+    // {"name": "value"}, {"name": ["value", "value"]} or [["name", "value"], ...]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Values {
+            Single(String),
+            Multiple(Vec<String>),
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Format {
+            Map(HashMap<String, Values>),
+            Pairs(Vec<(String, String)>),
+        }
+        let entries = match Format::deserialize(deserializer)? {
+            Format::Map(map) => map
+                .into_iter()
+                .flat_map(|(name, values)| {
+                    let values = match values {
+                        Values::Single(value) => vec![value],
+                        Values::Multiple(values) => values,
+                    };
+                    values
+                        .into_iter()
+                        .map(move |value| (name.to_lowercase(), value))
+                })
+                .collect(),
+            Format::Pairs(pairs) => pairs
+                .into_iter()
+                .map(|(name, value)| (name.to_lowercase(), value))
+                .collect(),
+        };
+        Ok(Self { entries })
+    }
+}
+
+impl garde::rules::length::HasSimpleLength for Headers {
+    fn length(&self) -> usize {
+        self.entries.len()
+    }
+}
 
 pub struct CatHeaderValidator {
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
+    // how a match rule applies to a header presented multiple times
+    pub match_mode: HeaderMatchMode,
 }
 
 impl Validate for CatHeaderValidator {
@@ -37,26 +107,25 @@ impl Validate for CatHeaderValidator {
                 .as_string()
                 .with_context(|| format!("Could not turn value at {} into string", i))?;
             let header_value = map.get(&j).and_then(|hv| hv.as_match_kind());
-            match header_value {
-                None => {
-                    if !self.headers.contains_key(&header_name) {
-                        return Err(Error::msg(format!(
-                            "Required HTTP Header {header_name} not presented"
-                        )));
-                    }
-                }
-                Some(mk) => {
-                    let value = self.headers.get(&header_name);
-                    if value.is_none() {
-                        return Err(Error::msg(format!(
-                            "Required HTTP Header {header_name} not presented"
-                        )));
-                    }
-                    if mk.validate(value.unwrap().clone()).is_err() {
-                        return Err(Error::msg(format!(
-                            "Presented HTTP Header {header_name} has invalid value"
-                        )));
-                    }
+            let values = self.headers.get_all(&header_name).collect::<Vec<&str>>();
+            if values.is_empty() {
+                return Err(Error::msg(format!(
+                    "Required HTTP Header {header_name} not presented"
+                )));
+            }
+            if let Some(mk) = header_value {
+                let matching = values
+                    .iter()
+                    .map(|value| mk.matches(value))
+                    .collect::<Result<Vec<bool>>>()?;
+                let valid = match self.match_mode {
+                    HeaderMatchMode::Any => matching.contains(&true),
+                    HeaderMatchMode::All => !matching.contains(&false),
+                };
+                if !valid {
+                    return Err(Error::msg(format!(
+                        "Presented HTTP Header {header_name} has invalid value"
+                    )));
                 }
             }
 
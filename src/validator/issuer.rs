@@ -0,0 +1,71 @@
+use anyhow::{Error, Result};
+use common_access_token::Token;
+
+use crate::persistence::IssuerPolicy;
+
+pub struct IssuerValidator<'a> {
+    policy: &'a IssuerPolicy,
+}
+
+impl<'a> From<&'a IssuerPolicy> for IssuerValidator<'a> {
+    fn from(value: &'a IssuerPolicy) -> Self {
+        Self { policy: value }
+    }
+}
+
+impl IssuerValidator<'_> {
+    fn kid(token: &Token) -> Option<String> {
+        token
+            .header
+            .key_id()
+            .map(|kid| String::from_utf8_lossy(kid.as_bytes()).to_string())
+    }
+
+    // kid of the token if the issuer knows it, tokens can't add arbitrary values to metrics
+    pub fn key_label(&self, token: &Token) -> String {
+        match Self::kid(token) {
+            None => String::from("none"),
+            Some(kid) if self.policy.keys.iter().any(|key| key.kid == kid) => kid,
+            Some(_) => String::from("unknown"),
+        }
+    }
+
+    pub fn verify_signature(&self, token: &Token) -> Result<()> {
+        let kid = Self::kid(token);
+        let verified = self
+            .policy
+            .keys
+            .iter()
+            // tokens without kid are checked against all keys of the issuer
+            .filter(|key| kid.as_ref().map_or(true, |kid| kid == &key.kid))
+            .any(|key| token.verify(key.secret.as_bytes()).is_ok());
+        match verified {
+            true => Ok(()),
+            false => Err(Error::msg("Token Signature Validation Failed")),
+        }
+    }
+
+    pub fn validate_claims(&self, token: &Token) -> Result<()> {
+        let claims = &token.claims.registered;
+
+        if !self.policy.audiences.is_empty()
+            && !claims
+                .aud
+                .as_ref()
+                .is_some_and(|aud| self.policy.audiences.contains(aud))
+        {
+            return Err(Error::msg("Audience not accepted by issuer"));
+        }
+
+        let all_claims = token.claims.to_map();
+        if let Some(missing) = self
+            .policy
+            .required_claims
+            .iter()
+            .find(|key| !all_claims.contains_key(key))
+        {
+            return Err(Error::msg(format!("Required claim {missing} not present")));
+        }
+        Ok(())
+    }
+}
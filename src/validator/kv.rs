@@ -1,15 +1,34 @@
-use std::net::IpAddr;
+use std::{fmt, net::IpAddr, rc::Rc};
 
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use common_access_token::current_timestamp;
 use ipnet::IpNet;
 
-use crate::persistence::BlockedData;
+use crate::{
+    geoip::ClientLocation,
+    persistence::{BlockedClaimType, BlockedData, RevokedTokens, UnknownLocationPolicy},
+    validator::{AsyncValidate, RequestContext, Stage},
+};
 
 pub struct KvValidator {
     blocked_data: BlockedData,
 }
 
 impl From<BlockedData> for KvValidator {
-    fn from(value: BlockedData) -> Self {
+    fn from(mut value: BlockedData) -> Self {
+        // countries stored before entries were normalized may be lowercase
+        if value
+            .countries
+            .iter()
+            .any(|country| country.bytes().any(|b| b.is_ascii_lowercase()))
+        {
+            for country in value.countries.iter_mut() {
+                *country = country.trim().to_uppercase();
+            }
+            value.countries.sort();
+            value.countries.dedup();
+        }
         Self {
             blocked_data: value,
         }
@@ -30,14 +49,31 @@ impl KvValidator {
             .is_ok()
     }
 
-    pub fn is_country_blocked(&self, value: &String) -> bool {
+    pub fn any_countries(&self) -> bool {
+        self.blocked_data.any_countries
+    }
+
+    pub fn is_country_blocked(&self, value: &str) -> bool {
         if !self.blocked_data.any_countries {
             return false;
         }
-        self.blocked_data.countries.binary_search(value).is_ok()
+        // blocklist entries are stored in their canonical (uppercase) form
+        let value = value.trim().to_uppercase();
+        // a blocked country also blocks all of its subdivisions
+        let country = value.split_once('-').map_or(value.as_str(), |(c, _)| c);
+        self.blocked_data
+            .countries
+            .binary_search_by(|blocked| blocked.as_str().cmp(&value))
+            .is_ok()
+            || self
+                .blocked_data
+                .countries
+                .binary_search_by(|blocked| blocked.as_str().cmp(country))
+                .is_ok()
     }
 
-    pub fn is_ip_blocked_by_asn(&self, value: &String) -> bool {
+    #[allow(dead_code)]
+    pub fn is_ip_blocked_by_asn(&self, value: &str) -> bool {
         if !self.blocked_data.any_asns {
             return false;
         }
@@ -50,7 +86,7 @@ impl KvValidator {
             .filter_map(|v| v.parse::<IpNet>().ok())
             .any(|cidr| cidr.contains(&actual_ip))
     }
-    pub fn is_ip_blocked(&self, value: &String) -> bool {
+    pub fn is_ip_blocked(&self, value: &str) -> bool {
         if !self.blocked_data.any_cidrs {
             return false;
         }
@@ -69,3 +105,160 @@ impl KvValidator {
         self.blocked_data.user_agents.binary_search(value).is_ok()
     }
 }
+
+/// Request matching a blocklist entry
+#[derive(Debug)]
+pub struct BlockedError {
+    pub category: BlockedClaimType,
+    reason: &'static str,
+}
+
+impl fmt::Display for BlockedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for BlockedError {}
+
+fn blocked(category: BlockedClaimType, reason: &'static str) -> Error {
+    Error::new(BlockedError { category, reason })
+}
+
+pub struct BlocklistValidator {
+    pub categories: Vec<BlockedClaimType>,
+    pub location: Rc<ClientLocation>,
+    // applied to the country blocklist if the location can't be determined
+    pub unknown_location: UnknownLocationPolicy,
+}
+
+#[async_trait(?Send)]
+impl AsyncValidate for BlocklistValidator {
+    fn stage(&self) -> Stage {
+        Stage::Blocklist
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let blocking_data = ctx.persistence.get_blocking_data()?;
+        if !blocking_data.any {
+            return Ok(());
+        }
+        let kv_validator = KvValidator::from(blocking_data);
+        let applies = |category| self.categories.contains(&category);
+        if applies(BlockedClaimType::Subject)
+            && kv_validator.is_subject_blocked(&ctx.claims.registered.sub, true)
+        {
+            return Err(blocked(BlockedClaimType::Subject, "Subject blocked"));
+        }
+        if applies(BlockedClaimType::Country) && kv_validator.any_countries() {
+            match self.location.country()? {
+                Some(country) if kv_validator.is_country_blocked(country) => {
+                    return Err(blocked(
+                        BlockedClaimType::Country,
+                        "Country or Region blocked",
+                    ));
+                }
+                None if self.unknown_location == UnknownLocationPolicy::Deny => {
+                    return Err(blocked(
+                        BlockedClaimType::Country,
+                        "Request origin unknown but required by the country blocklist",
+                    ));
+                }
+                _ => (),
+            }
+        }
+        if let Some(user_agent) = ctx
+            .headers
+            .get("User-Agent")
+            .filter(|_| applies(BlockedClaimType::UserAgent))
+        {
+            if kv_validator.is_user_agent_blocked(&user_agent.to_string()) {
+                return Err(blocked(BlockedClaimType::UserAgent, "User Agent blocked"));
+            }
+        }
+        if applies(BlockedClaimType::Cidr) && kv_validator.is_ip_blocked(ctx.client_ip) {
+            return Err(blocked(BlockedClaimType::Cidr, "IP address is blocked"));
+        }
+        Ok(())
+    }
+}
+
+pub struct RevocationValidator {}
+
+impl RevocationValidator {
+    fn is_token_revoked(revoked_tokens: &RevokedTokens, cti: &Option<Vec<u8>>) -> bool {
+        let Some(cti) = cti else {
+            // tokens without cti can't be revoked individually
+            return false;
+        };
+        if !revoked_tokens.any {
+            return false;
+        }
+        let cti = base64_url::encode(cti);
+        match revoked_tokens
+            .tokens
+            .binary_search_by(|revoked| revoked.cti.cmp(&cti))
+        {
+            Ok(idx) => !revoked_tokens.tokens[idx].is_expired(current_timestamp()),
+            Err(_) => false,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl AsyncValidate for RevocationValidator {
+    fn stage(&self) -> Stage {
+        Stage::Revocation
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let revoked_tokens = ctx.persistence.get_revoked_tokens()?;
+        match Self::is_token_revoked(&revoked_tokens, &ctx.claims.registered.cti) {
+            true => Err(Error::msg("Token revoked")),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::Region;
+
+    fn blocking_countries(countries: &[&str]) -> KvValidator {
+        let mut data = BlockedData::new();
+        data.countries = countries
+            .iter()
+            .map(|country| Region::canonical(country).unwrap())
+            .collect();
+        data.optimize();
+        KvValidator::from(data)
+    }
+
+    #[test]
+    fn blocks_subdivisions_regardless_of_case() {
+        let kv = blocking_countries(&["us-ca"]);
+        assert!(kv.is_country_blocked("US-CA"));
+        assert!(kv.is_country_blocked("us-ca"));
+        assert!(!kv.is_country_blocked("US-NY"));
+        assert!(!kv.is_country_blocked("US"));
+    }
+
+    #[test]
+    fn blocked_countries_cover_their_subdivisions() {
+        let kv = blocking_countries(&["de"]);
+        assert!(kv.is_country_blocked("DE"));
+        assert!(kv.is_country_blocked("DE-BY"));
+        assert!(!kv.is_country_blocked("AT"));
+    }
+
+    #[test]
+    fn normalizes_entries_stored_in_lowercase() {
+        let mut data = BlockedData::new();
+        data.countries = vec![String::from("us-ca"), String::from("fr")];
+        data.optimize();
+        let kv = KvValidator::from(data);
+        assert!(kv.is_country_blocked("US-CA"));
+        assert!(kv.is_country_blocked("FR-2A"));
+    }
+}
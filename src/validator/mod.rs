@@ -1,23 +1,100 @@
+mod alpn;
 mod cat;
+mod catif;
+mod catm;
+mod catu;
 mod country;
+mod crit;
+mod custom;
+mod geo;
 mod header;
+mod iso3166;
+mod issuer;
 mod kv;
 mod nip;
+mod observation;
+mod pipeline;
+mod pop;
+mod rate_limit;
+mod regex_cache;
+mod registry;
+mod replay;
+mod session;
+mod time;
 mod version;
 
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-use anyhow::{anyhow, Result};
-use common_access_token::CborValue;
+use anyhow::Result;
+use common_access_token::{cat_keys, cwt_keys, CborValue};
 use ipnet::IpNet;
-use regex::Regex;
 
+pub use alpn::*;
 pub use cat::*;
+pub use catif::ConditionalResponse;
+pub use catm::*;
+pub use catu::*;
 pub use country::*;
+pub use crit::*;
+pub use custom::*;
+pub use geo::*;
 pub use header::*;
+pub use iso3166::Region;
+pub use kv::BlockedError;
 pub use nip::*;
+pub use observation::{FailureCode, Observation, SpanRecord, Stage};
+pub use pipeline::{AsyncValidate, RequestContext};
+pub use pop::ProofError;
+pub use rate_limit::*;
+pub use registry::*;
+pub use time::TimeValidationOptions;
 pub use version::*;
 
+pub mod claim_keys {
+    /// Confirmation (cnf) claim key as defined by RFC 8747
+    pub const CNF: i32 = 8;
+    /// Critical claims (crit) claim key as defined by CTA-5007
+    pub const CRIT: i32 = 324;
+    /// Geohash (geohash) claim key
+    pub const GEOHASH: i32 = 282;
+}
+
+// claims understood by the validator itself, claim validators add their claim keys on top
+pub const SUPPORTED_CLAIMS: [i32; 13] = [
+    cwt_keys::ISS,
+    cwt_keys::SUB,
+    cwt_keys::AUD,
+    cwt_keys::EXP,
+    cwt_keys::NBF,
+    cwt_keys::IAT,
+    cwt_keys::CTI,
+    claim_keys::CNF,
+    claim_keys::CRIT,
+    cat_keys::CATPOR,
+    cat_keys::CATDPOP,
+    cat_keys::CATIF,
+    cat_keys::CATIFDATA,
+];
+
+// claims of the built-in claim validators, registered for every validation
+pub const BUILT_IN_CLAIM_VALIDATORS: [i32; 10] = [
+    cat_keys::CATV,
+    cat_keys::CATU,
+    cat_keys::CATM,
+    cat_keys::CATH,
+    cat_keys::CATNIP,
+    cat_keys::CATALPN,
+    cat_keys::CATGEOCOORD,
+    claim_keys::GEOHASH,
+    cat_keys::CATGEOALT,
+    cat_keys::CATGEOISO3166,
+];
+
+// claims validated by the validator itself, as opposed to private claims
+pub fn is_built_in_claim(claim_key: i32) -> bool {
+    SUPPORTED_CLAIMS.contains(&claim_key) || BUILT_IN_CLAIM_VALIDATORS.contains(&claim_key)
+}
+
 pub trait Validate {
     fn get_claim_key(&self) -> &i32;
     fn validate(&self, claim: Option<&CborValue>) -> Result<()>;
@@ -38,7 +115,8 @@ pub enum NetworkAddress {
     IPv4(Ipv4Addr),
     IPv6Prefix(IpNet),
     IPv6(Ipv6Addr),
-    ASN(u32),
+    #[allow(dead_code)]
+    Asn(u32),
 }
 
 pub enum MatchKind {
@@ -50,21 +128,15 @@ pub enum MatchKind {
 }
 
 impl MatchKind {
-    fn validate(&self, header_value: String) -> Result<()> {
-        let valid = match self {
-            MatchKind::Exact(expected) => &header_value == expected,
-            MatchKind::Prefix(expected) => header_value.starts_with(expected),
-            MatchKind::Suffix(expected) => header_value.ends_with(expected),
-            MatchKind::Contains(expected) => header_value.contains(expected),
-            MatchKind::RegEx(expected) => {
-                let r = Regex::new(expected).unwrap();
-                r.is_match(header_value.as_str())
-            }
-        };
-        match valid {
-            true => Ok(()),
-            false => Err(anyhow!("Header value not valid")),
-        }
+    // a pattern which can't be compiled fails the validation
+    fn matches(&self, value: &str) -> Result<bool> {
+        Ok(match self {
+            MatchKind::Exact(expected) => value == expected,
+            MatchKind::Prefix(expected) => value.starts_with(expected),
+            MatchKind::Suffix(expected) => value.ends_with(expected),
+            MatchKind::Contains(expected) => value.contains(expected),
+            MatchKind::RegEx(expected) => regex_cache::compile(expected)?.is_match(value),
+        })
     }
 }
 impl Convert for CborValue {
@@ -149,7 +221,7 @@ impl Convert for CborValue {
                             };
                             return None;
                         }
-                        0..=16 => {
+                        5..=16 => {
                             // IPv6 prefix
                             let mut octets = [0u8; 16];
                             octets[..prefix_bytes.len()].copy_from_slice(prefix_bytes);
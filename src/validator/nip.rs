@@ -31,7 +31,7 @@ impl Validate for CatNipValidator {
                     super::NetworkAddress::IPv4(ipv4_addr) => ipv4_addr.eq(&ip),
                     super::NetworkAddress::IPv6Prefix(ip_net) => ip_net.contains(&ip),
                     super::NetworkAddress::IPv6(ipv6_addr) => ipv6_addr.eq(&ip),
-                    super::NetworkAddress::ASN(_) => false,
+                    super::NetworkAddress::Asn(_) => false,
                 });
                 if valid {
                     return Ok(());
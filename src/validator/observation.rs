@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+use crate::persistence::BlockedClaimType;
+
+/// Outcome and timings of a single validation, the token itself is never recorded
+#[derive(Default)]
+pub struct Observation {
+    // accumulated duration per stage, in order of first appearance
+    pub stages: Vec<(Stage, Duration)>,
+    pub failure: Option<FailureCode>,
+    // claim a failure is attributed to
+    pub claim: Option<i32>,
+    // blocklist category a blocked request matched
+    pub blocked: Option<BlockedClaimType>,
+    // key the signature was checked against (kid of the token if known to the issuer)
+    pub kid: Option<String>,
+    // iss, sub and cti (base64url) of the token, only set once its signature is verified
+    pub issuer: Option<String>,
+    pub subject: Option<String>,
+    pub cti: Option<String>,
+    // the token has been presented before (catreplay reuse detection)
+    pub replayed: bool,
+    // steps of the pipeline in order of execution
+    pub spans: Vec<SpanRecord>,
+}
+
+pub struct SpanRecord {
+    pub name: String,
+    pub stage: Stage,
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub claim: Option<i32>,
+    pub failed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Decode,
+    Verify,
+    Revocation,
+    Blocklist,
+    RateLimit,
+    Claims,
+    Proof,
+    Session,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FailureCode {
+    Malformed,
+    UnknownIssuer,
+    InvalidSignature,
+    UnknownCriticalClaim,
+    InvalidTime,
+    IssuerPolicy,
+    AudienceRejected,
+    Revoked,
+    Blocked,
+    RateLimited,
+    InvalidProof,
+    InvalidClaim,
+    SessionLimit,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Verify => "verify",
+            Stage::Revocation => "revocation",
+            Stage::Blocklist => "blocklist",
+            Stage::RateLimit => "rate_limit",
+            Stage::Claims => "claims",
+            Stage::Proof => "proof",
+            Stage::Session => "session",
+        }
+    }
+}
+
+impl FailureCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureCode::Malformed => "malformed",
+            FailureCode::UnknownIssuer => "unknown_issuer",
+            FailureCode::InvalidSignature => "invalid_signature",
+            FailureCode::UnknownCriticalClaim => "unknown_critical_claim",
+            FailureCode::InvalidTime => "invalid_time",
+            FailureCode::IssuerPolicy => "issuer_policy",
+            FailureCode::AudienceRejected => "audience_rejected",
+            FailureCode::Revoked => "revoked",
+            FailureCode::Blocked => "blocked",
+            FailureCode::RateLimited => "rate_limited",
+            FailureCode::InvalidProof => "invalid_proof",
+            FailureCode::InvalidClaim => "invalid_claim",
+            FailureCode::SessionLimit => "session_limit",
+        }
+    }
+}
+
+impl Observation {
+    // runs a synchronous step of a stage, recording its span and the failure code
+    pub(super) fn run<T>(
+        &mut self,
+        name: &str,
+        stage: Stage,
+        code: FailureCode,
+        step: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let (start, started) = (SystemTime::now(), Instant::now());
+        let result = step();
+        self.record(SpanRecord {
+            name: name.to_string(),
+            stage,
+            start,
+            duration: started.elapsed(),
+            claim: None,
+            failed: result.is_err(),
+        });
+        if result.is_err() {
+            self.failure = Some(code);
+        }
+        result
+    }
+
+    pub(super) fn record(&mut self, span: SpanRecord) {
+        match self
+            .stages
+            .iter_mut()
+            .find(|(known, _)| *known == span.stage)
+        {
+            Some((_, duration)) => *duration += span.duration,
+            None => self.stages.push((span.stage, span.duration)),
+        }
+        self.spans.push(span);
+    }
+}
@@ -0,0 +1,85 @@
+use std::cell::{Cell, OnceCell};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use common_access_token::{cwt_keys, CborValue, Claims, RegisteredClaims};
+use spin_sdk::key_value::Store;
+
+use crate::{
+    persistence::Persistence,
+    validator::{Headers, Stage, Validate},
+};
+
+/// Everything known about the request a token is validated for
+pub struct RequestContext<'a> {
+    pub client_ip: &'a str,
+    pub url: &'a str,
+    pub method: &'a str,
+    pub headers: &'a Headers,
+    // claims of the (signature verified) token
+    pub claims: &'a Claims,
+    // opened on first use, pipelines without key-value backed checks never open the store
+    pub(super) store: OnceCell<Store>,
+    // set by catreplay reuse detection when the token has been presented before
+    pub(super) replayed: Cell<bool>,
+    // data of the tenant the request belongs to
+    pub persistence: &'a Persistence,
+}
+
+impl RequestContext<'_> {
+    pub fn store(&self) -> Result<&Store> {
+        if let Some(store) = self.store.get() {
+            return Ok(store);
+        }
+        let store = Store::open_default()?;
+        Ok(self.store.get_or_init(|| store))
+    }
+}
+
+/// Validators which need I/O (key-value store, outbound requests) or the entire request
+#[async_trait(?Send)]
+pub trait AsyncValidate {
+    // the claim a failure is attributed to (catif), if any
+    fn get_claim_key(&self) -> Option<&i32> {
+        None
+    }
+    // the stage the validator's duration is accounted to
+    fn stage(&self) -> Stage {
+        Stage::Claims
+    }
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()>;
+}
+
+// runs a claim validator as part of the pipeline
+pub(super) struct SyncValidator(pub Box<dyn Validate>);
+
+#[async_trait(?Send)]
+impl AsyncValidate for SyncValidator {
+    fn get_claim_key(&self) -> Option<&i32> {
+        Some(self.0.get_claim_key())
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let claim_key = self.0.get_claim_key();
+        match registered_claim(&ctx.claims.registered, *claim_key) {
+            Some(claim) => self.0.validate(claim.as_ref()),
+            None => self.0.validate(ctx.claims.custom.get(claim_key)),
+        }
+    }
+}
+
+// registered claims (RFC 8392) are decoded into their own fields instead of the custom claims,
+// None if the claim key isn't a registered claim
+fn registered_claim(registered: &RegisteredClaims, claim_key: i32) -> Option<Option<CborValue>> {
+    let integer = |value: Option<u64>| value.map(|value| CborValue::Integer(value as i64));
+    Some(match claim_key {
+        cwt_keys::ISS => registered.iss.clone().map(CborValue::Text),
+        cwt_keys::SUB => registered.sub.clone().map(CborValue::Text),
+        cwt_keys::AUD => registered.aud.clone().map(CborValue::Text),
+        cwt_keys::EXP => integer(registered.exp),
+        cwt_keys::NBF => integer(registered.nbf),
+        cwt_keys::IAT => integer(registered.iat),
+        cwt_keys::CTI => registered.cti.clone().map(CborValue::Bytes),
+        _ => return None,
+    })
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use common_access_token::{cat_keys, current_timestamp, CborValue};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::Deserialize;
+use url::Url;
+
+use crate::validator::{claim_keys, AsyncValidate, Convert, RequestContext, Stage};
+
+// default freshness window of a proof in seconds
+const DEFAULT_PROOF_WINDOW: u64 = 60;
+
+// COSE_Key parameters (RFC 9052) of an EC2 P-256 key
+const COSE_KEY_KTY: i32 = 1;
+const COSE_KEY_CRV: i32 = -1;
+const COSE_KEY_X: i32 = -2;
+const COSE_KEY_Y: i32 = -3;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_CRV_P256: i64 = 1;
+// confirmation method carrying a COSE_Key (RFC 8747)
+const CNF_COSE_KEY: i32 = 1;
+
+/// Failed proof of possession, reported separately from other validation failures
+#[derive(Debug)]
+pub struct ProofError(String);
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Proof of possession failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+fn proof_error(reason: &str) -> Error {
+    Error::new(ProofError(reason.to_string()))
+}
+
+struct ProofSettings {
+    // accepted age of a proof (iat) in seconds
+    window: u64,
+    // reject proofs whose jti has been presented before
+    reject_replay: bool,
+}
+
+impl ProofSettings {
+    // {0: window in seconds, 1: jti processing (0 = ignore, 1 = reject replays)}
+    fn from_claim(value: &CborValue) -> Result<Self> {
+        let settings = match value {
+            CborValue::Map(settings) => settings,
+            // catpor may be a plain flag
+            _ => return Ok(Self::default()),
+        };
+        let window = match settings.get(&0) {
+            None => DEFAULT_PROOF_WINDOW,
+            Some(window) => window
+                .as_i64()
+                .and_then(|window| u64::try_from(window).ok())
+                .ok_or(Error::msg("Invalid proof window specified in token"))?,
+        };
+        let reject_replay = match settings.get(&1) {
+            None => true,
+            Some(CborValue::Integer(0)) => false,
+            Some(CborValue::Integer(1)) => true,
+            Some(_) => return Err(Error::msg("Invalid jti processing specified in token")),
+        };
+        Ok(Self {
+            window,
+            reject_replay,
+        })
+    }
+}
+
+impl Default for ProofSettings {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_PROOF_WINDOW,
+            reject_replay: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProofHeader {
+    typ: String,
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct ProofClaims {
+    htm: String,
+    htu: String,
+    iat: u64,
+    jti: String,
+    nonce: Option<String>,
+}
+
+pub struct PopValidator {
+    // nonce handed out to the client, the proof has to echo it
+    pub nonce: Option<String>,
+    pub check_replay: bool,
+}
+
+#[async_trait(?Send)]
+impl AsyncValidate for PopValidator {
+    fn stage(&self) -> Stage {
+        Stage::Proof
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let claims = &ctx.claims.custom;
+        // catdpop carries the proof settings, catpor only requires the proof
+        let settings = match (
+            claims.get(&cat_keys::CATDPOP),
+            claims.get(&cat_keys::CATPOR),
+        ) {
+            (None, None) => return Ok(()), // token is not bound to a key
+            (Some(settings), _) | (None, Some(settings)) => ProofSettings::from_claim(settings)?,
+        };
+        let key = claims
+            .get(&claim_keys::CNF)
+            .and_then(Self::as_verifying_key)
+            .ok_or(Error::msg(
+                "Token requires proof of possession but has no valid confirmation key (cnf)",
+            ))?;
+        // DPoP proof JWT presented with the request
+        let Some(proof) = ctx.headers.get("DPoP") else {
+            return Err(proof_error("no proof presented"));
+        };
+        let claims = self.check_proof(proof, &key, ctx.method, ctx.url, &settings)?;
+        self.register_jti(&settings, &claims, |jti, exp| {
+            ctx.persistence.register_proof(ctx.store()?, jti, exp)
+        })
+    }
+}
+
+impl PopValidator {
+    // verifies the proof and checks it against the request, jti replays aside
+    fn check_proof(
+        &self,
+        proof: &str,
+        key: &VerifyingKey,
+        method: &str,
+        url: &str,
+        settings: &ProofSettings,
+    ) -> Result<ProofClaims> {
+        let claims = Self::verify_proof(proof, key)?;
+        if !claims.htm.eq_ignore_ascii_case(method) {
+            return Err(proof_error("htm does not match request method"));
+        }
+        let htu = normalize_htu(&claims.htu);
+        if htu.is_none() || htu != normalize_htu(url) {
+            return Err(proof_error("htu does not match request URL"));
+        }
+        if current_timestamp().abs_diff(claims.iat) > settings.window {
+            return Err(proof_error("proof is not fresh"));
+        }
+        if let Some(nonce) = &self.nonce {
+            if claims.nonce.as_ref() != Some(nonce) {
+                return Err(proof_error("nonce does not match"));
+            }
+        }
+        Ok(claims)
+    }
+
+    // register records the jti until the given expiry, returns false if it has been seen before
+    fn register_jti(
+        &self,
+        settings: &ProofSettings,
+        claims: &ProofClaims,
+        register: impl FnOnce(&str, u64) -> Result<bool>,
+    ) -> Result<()> {
+        if !settings.reject_replay {
+            return Ok(());
+        }
+        if !self.check_replay {
+            // proofs requiring replay protection can't be accepted without tracking jtis
+            return Err(proof_error("replay protection (jti) not supported"));
+        }
+        // window is taken from the token, the expiry must not overflow
+        let exp = claims.iat.saturating_add(settings.window);
+        if !register(&claims.jti, exp)? {
+            return Err(proof_error("proof has been presented before (jti)"));
+        }
+        Ok(())
+    }
+
+    // {1: COSE_Key} with an EC2 P-256 key
+    fn as_verifying_key(cnf: &CborValue) -> Option<VerifyingKey> {
+        let CborValue::Map(cnf) = cnf else {
+            return None;
+        };
+        let CborValue::Map(key) = cnf.get(&CNF_COSE_KEY)? else {
+            return None;
+        };
+        if key.get(&COSE_KEY_KTY)?.as_i64()? != COSE_KTY_EC2
+            || key.get(&COSE_KEY_CRV)?.as_i64()? != COSE_CRV_P256
+        {
+            return None;
+        }
+        let (CborValue::Bytes(x), CborValue::Bytes(y)) =
+            (key.get(&COSE_KEY_X)?, key.get(&COSE_KEY_Y)?)
+        else {
+            return None;
+        };
+        let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+        VerifyingKey::from_sec1_bytes(&point).ok()
+    }
+
+    fn verify_proof(proof: &str, key: &VerifyingKey) -> Result<ProofClaims> {
+        let mut parts = proof.trim().split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(proof_error("malformed proof"));
+        };
+        let decoded_header = base64_url::decode(header)
+            .ok()
+            .and_then(|header| serde_json::from_slice::<ProofHeader>(&header).ok())
+            .ok_or(proof_error("malformed proof header"))?;
+        if !decoded_header.typ.eq_ignore_ascii_case("dpop+jwt") || decoded_header.alg != "ES256" {
+            return Err(proof_error("unsupported proof type or algorithm"));
+        }
+        let signature = base64_url::decode(signature)
+            .ok()
+            .and_then(|signature| Signature::from_slice(&signature).ok())
+            .ok_or(proof_error("malformed proof signature"))?;
+        key.verify(format!("{header}.{payload}").as_bytes(), &signature)
+            .map_err(|_| proof_error("invalid proof signature"))?;
+        base64_url::decode(payload)
+            .ok()
+            .and_then(|payload| serde_json::from_slice::<ProofClaims>(&payload).ok())
+            .ok_or(proof_error("malformed proof claims"))
+    }
+}
+
+// htu is compared without query and fragment after syntax and scheme based normalization
+// (RFC 9449 section 4.3), e.g. case of scheme and host, default ports and percent-encoding
+fn normalize_htu(url: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    url.set_query(None);
+    url.set_fragment(None);
+    Some(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+
+    const URL: &str = "https://example.com/resource";
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
+
+    // cnf claim carrying the public key of signing_key()
+    fn cnf() -> CborValue {
+        let point = signing_key().verifying_key().to_encoded_point(false);
+        let key = [
+            (COSE_KEY_KTY, CborValue::Integer(COSE_KTY_EC2)),
+            (COSE_KEY_CRV, CborValue::Integer(COSE_CRV_P256)),
+            (COSE_KEY_X, CborValue::Bytes(point.x().unwrap().to_vec())),
+            (COSE_KEY_Y, CborValue::Bytes(point.y().unwrap().to_vec())),
+        ];
+        CborValue::Map([(CNF_COSE_KEY, CborValue::Map(key.into()))].into())
+    }
+
+    fn sign(header: serde_json::Value, payload: serde_json::Value) -> String {
+        let input = format!(
+            "{}.{}",
+            base64_url::encode(&header.to_string()),
+            base64_url::encode(&payload.to_string())
+        );
+        let signature: Signature = signing_key().sign(input.as_bytes());
+        format!("{input}.{}", base64_url::encode(&signature.to_bytes()))
+    }
+
+    fn proof(htm: &str, htu: &str, iat: u64, jti: &str) -> String {
+        sign(
+            json!({"typ": "dpop+jwt", "alg": "ES256"}),
+            json!({"htm": htm, "htu": htu, "iat": iat, "jti": jti}),
+        )
+    }
+
+    fn check(proof: &str) -> Result<ProofClaims> {
+        let key = PopValidator::as_verifying_key(&cnf()).unwrap();
+        validator(true).check_proof(proof, &key, "GET", URL, &ProofSettings::default())
+    }
+
+    fn is_proof_error(result: Result<ProofClaims>) -> bool {
+        result.is_err_and(|e| e.downcast_ref::<ProofError>().is_some())
+    }
+
+    fn claims(iat: u64, jti: &str) -> ProofClaims {
+        ProofClaims {
+            htm: String::from("GET"),
+            htu: String::from("https://example.com/"),
+            iat,
+            jti: String::from(jti),
+            nonce: None,
+        }
+    }
+
+    fn validator(check_replay: bool) -> PopValidator {
+        PopValidator {
+            nonce: None,
+            check_replay,
+        }
+    }
+
+    #[test]
+    fn replay_protection_requires_tracking() {
+        let settings = ProofSettings::default();
+        let claims = claims(current_timestamp(), "proof-1");
+        let result = validator(false).register_jti(&settings, &claims, |_, _| Ok(true));
+        assert!(result.unwrap_err().downcast_ref::<ProofError>().is_some());
+        // replay protection waived by the token
+        let settings = ProofSettings {
+            reject_replay: false,
+            ..ProofSettings::default()
+        };
+        assert!(validator(false)
+            .register_jti(&settings, &claims, |_, _| Ok(true))
+            .is_ok());
+    }
+
+    #[test]
+    fn jti_expiry_saturates() {
+        let settings =
+            ProofSettings::from_claim(&CborValue::Map([(0, CborValue::Integer(i64::MAX))].into()))
+                .unwrap();
+        let mut exp = 0;
+        validator(true)
+            .register_jti(&settings, &claims(u64::MAX - 1, "proof-1"), |_, value| {
+                exp = value;
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(exp, u64::MAX);
+    }
+
+    #[test]
+    fn accepts_valid_proofs() {
+        let claims = check(&proof("get", URL, current_timestamp(), "proof-1")).unwrap();
+        assert_eq!(claims.jti, "proof-1");
+    }
+
+    #[test]
+    fn rejects_unsupported_type_or_algorithm() {
+        let payload =
+            json!({"htm": "GET", "htu": URL, "iat": current_timestamp(), "jti": "proof-1"});
+        for header in [
+            json!({"typ": "JWT", "alg": "ES256"}),
+            json!({"typ": "dpop+jwt", "alg": "ES384"}),
+            json!({"typ": "dpop+jwt", "alg": "none"}),
+        ] {
+            assert!(is_proof_error(check(&sign(header, payload.clone()))));
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_proofs() {
+        // signature of a different proof
+        let get = proof("GET", URL, current_timestamp(), "proof-1");
+        let post = proof("POST", URL, current_timestamp(), "proof-1");
+        let (input, _) = get.rsplit_once('.').unwrap();
+        let (_, signature) = post.rsplit_once('.').unwrap();
+        assert!(is_proof_error(check(&format!("{input}.{signature}"))));
+        assert!(is_proof_error(check("not-a-proof")));
+    }
+
+    #[test]
+    fn rejects_method_mismatch() {
+        let proof = proof("POST", URL, current_timestamp(), "proof-1");
+        assert!(is_proof_error(check(&proof)));
+    }
+
+    #[test]
+    fn compares_htu_without_query_and_fragment() {
+        let now = current_timestamp();
+        for htu in [
+            "https://example.com/resource?page=2",
+            "https://example.com/resource#top",
+            "HTTPS://EXAMPLE.COM:443/resource",
+        ] {
+            assert!(check(&proof("GET", htu, now, "proof-1")).is_ok(), "{htu}");
+        }
+        for htu in [
+            "https://example.com/other",
+            "http://example.com/resource",
+            "https://example.org/resource",
+        ] {
+            assert!(
+                is_proof_error(check(&proof("GET", htu, now, "proof-1"))),
+                "{htu}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_stale_proofs() {
+        let now = current_timestamp();
+        assert!(is_proof_error(check(&proof(
+            "GET",
+            URL,
+            now - 61,
+            "proof-1"
+        ))));
+        assert!(is_proof_error(check(&proof(
+            "GET",
+            URL,
+            now + 61,
+            "proof-1"
+        ))));
+        assert!(check(&proof("GET", URL, now - 30, "proof-1")).is_ok());
+    }
+
+    #[test]
+    fn rejects_replayed_jtis() {
+        let settings = ProofSettings::default();
+        let validator = validator(true);
+        let mut used = HashSet::new();
+        let mut register = |claims: &ProofClaims| {
+            validator.register_jti(&settings, claims, |jti, _| Ok(used.insert(jti.to_string())))
+        };
+        let now = current_timestamp();
+        let first = check(&proof("GET", URL, now, "proof-1")).unwrap();
+        assert!(register(&first).is_ok());
+        let replayed = check(&proof("GET", URL, now, "proof-1")).unwrap();
+        assert!(register(&replayed).is_err_and(|e| e.downcast_ref::<ProofError>().is_some()));
+        let other = check(&proof("GET", URL, now, "proof-2")).unwrap();
+        assert!(register(&other).is_ok());
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+
+use crate::{
+    persistence::{RateLimit, RateLimitKey},
+    validator::{AsyncValidate, RequestContext, Stage},
+};
+
+/// Validation rejected because a rate limit has been exceeded
+#[derive(Debug)]
+pub struct RateLimitError {
+    // seconds until the request is allowed again
+    pub retry_after: u64,
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rate limit exceeded, retry after {} seconds",
+            self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for RateLimitError {}
+
+pub struct RateLimitValidator {
+    // counters are kept per profile
+    pub profile: String,
+    pub limits: Vec<RateLimit>,
+}
+
+#[async_trait(?Send)]
+impl AsyncValidate for RateLimitValidator {
+    fn stage(&self) -> Stage {
+        Stage::RateLimit
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let registered = &ctx.claims.registered;
+        let mut hits = vec![];
+        for limit in &self.limits {
+            // limits on attributes the request doesn't have are skipped
+            let (kind, value) = match limit.key {
+                RateLimitKey::Subject => ("sub", registered.sub.clone()),
+                RateLimitKey::Cti => ("cti", registered.cti.as_ref().map(base64_url::encode)),
+                RateLimitKey::ClientIp => ("ip", Some(ctx.client_ip.to_string())),
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            let counter = format!("{}/{kind}/{value}", self.profile);
+            let hit = ctx.persistence.hit_rate_limit(
+                ctx.store()?,
+                &counter,
+                limit.limit,
+                limit.window,
+            )?;
+            let retry_after = hit.retry_after;
+            hits.push(hit);
+            if let Some(retry_after) = retry_after {
+                // a rejected request isn't counted by any of the limits
+                for hit in &hits {
+                    ctx.persistence.release_rate_limit(hit)?;
+                }
+                return Err(Error::new(RateLimitError { retry_after }));
+            }
+        }
+        Ok(())
+    }
+}
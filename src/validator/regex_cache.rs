@@ -0,0 +1,80 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+};
+
+use anyhow::{Error, Result};
+use regex::{Regex, RegexBuilder};
+
+// limits for patterns supplied by tokens
+const MAX_PATTERN_LENGTH: usize = 1024;
+const MAX_COMPILED_SIZE: usize = 256 * 1024;
+const MAX_NESTING: u32 = 32;
+// number of compiled patterns kept, the oldest one is evicted first
+const CACHE_CAPACITY: usize = 128;
+
+// Spin creates a fresh instance for every request, so the cache only dedupes compilations
+// within one validation (e.g. a cath pattern matched against repeated headers)
+thread_local! {
+    static CACHE: RefCell<RegexCache> = RefCell::new(RegexCache {
+        compiled: HashMap::new(),
+        order: VecDeque::new(),
+    });
+}
+
+struct RegexCache {
+    compiled: HashMap<String, Regex>,
+    order: VecDeque<String>,
+}
+
+// compiles a token-supplied pattern within the limits, compiled patterns are reused for the
+// rest of the request
+pub fn compile(pattern: &str) -> Result<Regex> {
+    if let Some(regex) = CACHE.with(|cache| cache.borrow().compiled.get(pattern).cloned()) {
+        return Ok(regex);
+    }
+    if pattern.len() > MAX_PATTERN_LENGTH {
+        return Err(Error::msg(format!(
+            "Regular expression exceeds maximum length of {MAX_PATTERN_LENGTH}"
+        )));
+    }
+    let regex = RegexBuilder::new(pattern)
+        .size_limit(MAX_COMPILED_SIZE)
+        .dfa_size_limit(MAX_COMPILED_SIZE)
+        .nest_limit(MAX_NESTING)
+        .build()
+        .map_err(|_| Error::msg("Invalid or too complex regular expression"))?;
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.order.len() >= CACHE_CAPACITY {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.compiled.remove(&oldest);
+            }
+        }
+        cache.order.push_back(pattern.to_string());
+        cache.compiled.insert(pattern.to_string(), regex.clone());
+    });
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_patterns() {
+        let regex = compile("^/media/[a-z]+\\.m3u8$").unwrap();
+        assert!(regex.is_match("/media/foo.m3u8"));
+        assert!(!regex.is_match("/media/foo.mp4"));
+        // the compiled pattern is reused
+        assert!(compile("^/media/[a-z]+\\.m3u8$").is_ok());
+    }
+
+    #[test]
+    fn rejects_patterns_exceeding_the_limits() {
+        assert!(compile(&"a".repeat(MAX_PATTERN_LENGTH + 1)).is_err());
+        assert!(compile(&format!("{}a{}", "(".repeat(64), ")".repeat(64))).is_err());
+        assert!(compile("\\w{1000}\\w{1000}").is_err());
+        assert!(compile("(unclosed").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Error, Result};
+use common_access_token::CborValue;
+
+use crate::{persistence::ClaimPolicy, validator::Validate};
+
+/// Claim validators keyed by claim key
+#[derive(Default)]
+pub struct ValidatorRegistry {
+    validators: BTreeMap<i32, Box<dyn Validate>>,
+}
+
+impl ValidatorRegistry {
+    // registering a validator for a claim key replaces the existing one
+    pub fn register(&mut self, validator: Box<dyn Validate>) {
+        self.validators
+            .insert(*validator.get_claim_key(), validator);
+    }
+
+    // validators of all enabled claims, claims marked as required have to be present
+    pub fn build(mut self, claims: &BTreeMap<i32, ClaimPolicy>) -> Vec<Box<dyn Validate>> {
+        for (key, policy) in claims {
+            if !policy.enabled {
+                self.validators.remove(key);
+            }
+        }
+        let mut validators = self
+            .validators
+            .into_iter()
+            .map(|(key, validator)| match claims.get(&key) {
+                Some(policy) if policy.required => Box::new(RequiredClaimValidator {
+                    claim_key: key,
+                    validator: Some(validator),
+                }),
+                _ => validator,
+            })
+            .collect::<Vec<_>>();
+        // required claims without a validator only have to be present
+        validators.extend(
+            claims
+                .iter()
+                .filter(|(key, policy)| {
+                    policy.enabled
+                        && policy.required
+                        && !validators.iter().any(|v| v.get_claim_key() == *key)
+                })
+                .map(|(key, _)| {
+                    Box::new(RequiredClaimValidator {
+                        claim_key: *key,
+                        validator: None,
+                    }) as Box<dyn Validate>
+                })
+                .collect::<Vec<_>>(),
+        );
+        validators
+    }
+}
+
+struct RequiredClaimValidator {
+    claim_key: i32,
+    validator: Option<Box<dyn Validate>>,
+}
+
+impl Validate for RequiredClaimValidator {
+    fn get_claim_key(&self) -> &i32 {
+        &self.claim_key
+    }
+
+    fn validate(&self, claim: Option<&CborValue>) -> Result<()> {
+        if claim.is_none() {
+            return Err(Error::msg(format!(
+                "Required claim {} not present",
+                self.claim_key
+            )));
+        }
+        match &self.validator {
+            Some(validator) => validator.validate(claim),
+            None => Ok(()),
+        }
+    }
+}
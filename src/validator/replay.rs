@@ -0,0 +1,56 @@
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use common_access_token::{cat_keys, replay_values, CborValue};
+
+use crate::validator::{AsyncValidate, RequestContext};
+
+// catreplay restricts how often a token (identified by its cti) may be presented
+pub struct CatReplayValidator {
+    // whether the profile tracks presented tokens in the key-value store
+    pub track_uses: bool,
+}
+
+#[async_trait(?Send)]
+impl AsyncValidate for CatReplayValidator {
+    fn get_claim_key(&self) -> Option<&i32> {
+        Some(&cat_keys::CATREPLAY)
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let value = match ctx.claims.custom.get(&cat_keys::CATREPLAY) {
+            None => return Ok(()),
+            Some(CborValue::Integer(value)) => *value,
+            Some(_) => return Err(Error::msg("Invalid type value specified for CATREPLAY")),
+        };
+        let prohibited = match i32::try_from(value) {
+            Ok(replay_values::PERMITTED) => return Ok(()),
+            Ok(replay_values::PROHIBITED) => true,
+            Ok(replay_values::REUSE_DETECTION) => false,
+            _ => return Err(Error::msg("Invalid value specified for CATREPLAY")),
+        };
+        if !self.track_uses {
+            // without tracking, only reuse detection can be waived
+            return match prohibited {
+                true => Err(Error::msg("Replay protection (CATREPLAY) not supported")),
+                false => Ok(()),
+            };
+        }
+        let registered = &ctx.claims.registered;
+        let Some(cti) = &registered.cti else {
+            return Err(Error::msg("CATREPLAY requires a token identifier (cti)"));
+        };
+        // tokens without exp are remembered for good
+        let exp = registered.exp.unwrap_or(u64::MAX);
+        if ctx.persistence.register_token_use(ctx.store()?, cti, exp)? {
+            return Ok(());
+        }
+        match prohibited {
+            true => Err(Error::msg("Token has been presented before (CATREPLAY)")),
+            // reused tokens are accepted but reported by the decision log
+            false => {
+                ctx.replayed.set(true);
+                Ok(())
+            }
+        }
+    }
+}
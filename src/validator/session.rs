@@ -0,0 +1,48 @@
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+
+use crate::{
+    persistence::{SessionOutcome, SessionPolicy},
+    validator::{AsyncValidate, RequestContext, Stage},
+};
+
+pub struct SessionValidator {
+    pub policy: SessionPolicy,
+}
+
+#[async_trait(?Send)]
+impl AsyncValidate for SessionValidator {
+    fn stage(&self) -> Stage {
+        Stage::Session
+    }
+
+    async fn validate(&self, ctx: &RequestContext<'_>) -> Result<()> {
+        let registered = &ctx.claims.registered;
+        // sessions are counted per subject, tokens without one would escape the limit
+        let Some(subject) = &registered.sub else {
+            return Err(Error::msg(
+                "Token has no subject (sub) to count sessions for",
+            ));
+        };
+        let id = match &self.policy.session_header {
+            Some(name) => ctx.headers.get(name).map(String::from),
+            None => registered.cti.as_ref().map(base64_url::encode),
+        };
+        let Some(id) = id else {
+            return Err(Error::msg("Session id not presented"));
+        };
+        match ctx.persistence.register_session(
+            subject,
+            &id,
+            self.policy.max_sessions,
+            self.policy.ttl,
+        )? {
+            SessionOutcome::Active => Ok(()),
+            SessionOutcome::LimitReached => Err(Error::msg(format!(
+                "Limit of {} concurrent sessions reached",
+                self.policy.max_sessions
+            ))),
+            SessionOutcome::Terminated => Err(Error::msg("Session terminated")),
+        }
+    }
+}
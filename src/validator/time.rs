@@ -0,0 +1,106 @@
+use anyhow::{Error, Result};
+use common_access_token::RegisteredClaims;
+
+use crate::persistence::IssuerPolicy;
+
+#[derive(Clone, Default)]
+pub struct TimeValidationOptions {
+    pub validate_expiration: bool,
+    pub validate_not_before: bool,
+    // leeway in seconds, falls back to the issuer policy if not set
+    pub exp_leeway: Option<u64>,
+    pub nbf_leeway: Option<u64>,
+    pub iat_leeway: Option<u64>,
+    // maximum allowed lifetime (exp - iat) in seconds, the stricter of this and the issuer policy applies
+    pub max_token_lifetime: Option<u64>,
+    // reject tokens without exp, applies if either this or the issuer policy requires it
+    pub require_expiration: bool,
+}
+
+impl TimeValidationOptions {
+    pub fn with_policy(self, policy: &IssuerPolicy) -> Self {
+        Self {
+            validate_expiration: self.validate_expiration,
+            validate_not_before: self.validate_not_before,
+            exp_leeway: self
+                .exp_leeway
+                .or(policy.exp_leeway)
+                .or(Some(policy.clock_skew)),
+            nbf_leeway: self
+                .nbf_leeway
+                .or(policy.nbf_leeway)
+                .or(Some(policy.clock_skew)),
+            iat_leeway: self
+                .iat_leeway
+                .or(policy.iat_leeway)
+                .or(Some(policy.clock_skew)),
+            max_token_lifetime: match (self.max_token_lifetime, policy.max_token_lifetime) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+            require_expiration: self.require_expiration || policy.require_expiration,
+        }
+    }
+}
+
+pub struct TimeValidator {
+    opts: TimeValidationOptions,
+}
+
+impl From<TimeValidationOptions> for TimeValidator {
+    fn from(value: TimeValidationOptions) -> Self {
+        Self { opts: value }
+    }
+}
+
+impl TimeValidator {
+    pub fn validate(&self, claims: &RegisteredClaims, now: u64) -> Result<()> {
+        let exp_leeway = self.opts.exp_leeway.unwrap_or_default();
+        let nbf_leeway = self.opts.nbf_leeway.unwrap_or_default();
+        let iat_leeway = self.opts.iat_leeway.unwrap_or_default();
+
+        match claims.exp {
+            None if self.opts.require_expiration => {
+                return Err(Error::msg("Token has no expiration (exp)"))
+            }
+            Some(exp) if self.opts.validate_expiration && now >= exp.saturating_add(exp_leeway) => {
+                return Err(Error::msg("Token expired"))
+            }
+            _ => {}
+        }
+
+        if self.opts.validate_not_before
+            && claims
+                .nbf
+                .is_some_and(|nbf| now.saturating_add(nbf_leeway) < nbf)
+        {
+            return Err(Error::msg("Token not yet valid"));
+        }
+
+        if claims
+            .iat
+            .is_some_and(|iat| now.saturating_add(iat_leeway) < iat)
+        {
+            return Err(Error::msg("Token issued in the future (iat)"));
+        }
+
+        if let Some(max_lifetime) = self.opts.max_token_lifetime {
+            let Some(exp) = claims.exp else {
+                return Err(Error::msg(
+                    "Token without expiration exceeds maximum lifetime",
+                ));
+            };
+            let Some(issued_at) = claims.iat.or(claims.nbf) else {
+                return Err(Error::msg(
+                    "Token lifetime can't be determined (neither iat nor nbf present)",
+                ));
+            };
+            if exp.saturating_sub(issued_at) > max_lifetime {
+                return Err(Error::msg(format!(
+                    "Token lifetime exceeds maximum of {max_lifetime} seconds"
+                )));
+            }
+        }
+        Ok(())
+    }
+}